@@ -20,10 +20,28 @@ use std::io::Write;
 
 use crate::output_module::OutputModule;
 use crate::ct_utils::despecialise;
+use crate::filetree_info::{EntryKind, FileTreeInfo};
+use crate::journal;
 
 pub struct BatchModule {
       filename: String,
-      output_file: BufWriter<File>
+      output_file: BufWriter<File>,
+      /// Reference tree is not available on the machine running the script
+      offline_reference: bool
+}
+
+fn dump_offline_duplicated(output_file: &mut BufWriter<File>, reference: &str, other: &str, directory: bool) -> Result<(), std::io::Error> {
+            let remove = despecialise(other);
+            output_file.write_all(format!("# {} is in offline reference\n", despecialise(reference)).as_bytes())?;
+            if directory {
+                  output_file.write_all(format!("# {} not removed : content of offline reference directory can not be checked\n\n", remove).as_bytes())?;
+                  return Ok(());
+            }
+            output_file.write_all(format!("if [ -e {} ]\n", remove).as_bytes())?;
+            output_file.write_all("then\n".as_bytes())?;
+            output_file.write_all(format!("    rm {}\n", remove).as_bytes())?;
+            output_file.write_all("fi\n\n".as_bytes())?;
+            Ok(())
 }
 
 fn dump_duplicated(output_file: &mut BufWriter<File>, reference: &str, other: &str) -> Result<(), std::io::Error> {
@@ -41,29 +59,84 @@ fn dump_duplicated(output_file: &mut BufWriter<File>, reference: &str, other: &s
             Ok(())
 }
 
+/// Directory is only removed if, when script is run, it still has the same number of entries and each of them
+/// is still identical to the copy found in reference directory when script was generated
+fn dump_duplicated_directory(output_file: &mut BufWriter<File>, reference: &str, other: &FileTreeInfo) -> Result<(), std::io::Error> {
+            let keep = despecialise(reference);
+            let remove = despecialise(&other.name);
+            output_file.write_all(format!("# {} : {} items, {} bytes\n", remove, other.nb_item, other.size).as_bytes())?;
+            let mut copies = match journal::directory_copies(reference, &other.name) {
+                  Ok(copies) => copies,
+                  Err(e) => {
+                        output_file.write_all(format!("# {} not removed : {}\n\n", remove, e).as_bytes())?;
+                        return Ok(());
+                  }
+            };
+            copies.sort_by(|a, b| a.name.cmp(&b.name));
+            output_file.write_all(format!("if [ ! -L {} -a -d {} ]\n", keep, keep).as_bytes())?;
+            output_file.write_all("then\n".as_bytes())?;
+            output_file.write_all(format!("    if [ $(find {} ! -type d -printf x | wc -c) -eq {} ]", remove, copies.len()).as_bytes())?;
+            for copy in &copies {
+                  let copy_name = despecialise(&copy.name.to_string_lossy());
+                  let reference_name = despecialise(&copy.reference.to_string_lossy());
+                  if copy.metadata.is_symlink() {
+                        output_file.write_all(format!(" \\\n        && [ -L {} ] && [ \"$(readlink {})\" = \"$(readlink {})\" ]", copy_name, reference_name, copy_name).as_bytes())?;
+                  } else {
+                        output_file.write_all(format!(" \\\n        && [ ! -L {} ] && cmp -s {} {}", copy_name, reference_name, copy_name).as_bytes())?;
+                  }
+            }
+            output_file.write_all("\n    then\n".as_bytes())?;
+            output_file.write_all(format!("        rm -r {}\n", remove).as_bytes())?;
+            output_file.write_all("    else\n".as_bytes())?;
+            output_file.write_all(format!(r#"        echo "{}" differs from "{}"{}"#, remove, keep, "\n").as_bytes())?;
+            output_file.write_all("    fi\n".as_bytes())?;
+            output_file.write_all(format!("elif [ -L {}  ]\n", keep).as_bytes())?;
+            output_file.write_all("then\n".as_bytes())?;
+            output_file.write_all(format!(r#"    echo "{}" is a link{}"#, keep, "\n").as_bytes())?;
+            output_file.write_all("else\n".as_bytes())?;
+            output_file.write_all(format!(r#"    echo "{}" do not exist{}"#, keep, "\n").as_bytes())?;
+            output_file.write_all("fi\n\n".as_bytes())?;
+            Ok(())
+}
+
 impl OutputModule for BatchModule {
-      fn treat_internal_doublon(&mut self, first: &str, second: &str) {
-            self.output_file.write(format!("# Doublon {} <-> {}\n\n", despecialise(first), despecialise(second)).as_bytes()).expect(format!("Unable to write in file {}", self.filename).as_str());
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            self.output_file.write(format!("# Doublon {} <-> {}\n\n", despecialise(&first.name), despecialise(&second.name)).as_bytes()).expect(format!("Unable to write in file {}", self.filename).as_str());
       }
-      fn treat_duplicated(&mut self, reference: &str, other: &str) -> Result<bool, String> {
-            dump_duplicated(&mut self.output_file, reference, other).expect(format!("Error during write of file {}", self.filename).as_str());
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            let directory = other.kind == EntryKind::Directory;
+            if self.offline_reference {
+                  dump_offline_duplicated(&mut self.output_file, &reference.name, &other.name, directory).unwrap_or_else(|e| panic!("Error during write of file {} : {}", self.filename, e));
+                  return Ok(true);
+            }
+            if directory {
+                  dump_duplicated_directory(&mut self.output_file, &reference.name, other).unwrap_or_else(|e| panic!("Error during write of file {} : {}", self.filename, e));
+                  return Ok(true);
+            }
+            dump_duplicated(&mut self.output_file, &reference.name, &other.name).expect(format!("Error during write of file {}", self.filename).as_str());
             Ok(true)
       }
+      fn treat_hardlinked(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            let line = format!("# Hardlinked {} <-> {}\n\n", despecialise(&first.name), despecialise(&second.name));
+            self.output_file.write_all(line.as_bytes()).unwrap_or_else(|e| panic!("Unable to write in file {} : {}", self.filename, e));
+      }
 }
 
 impl Drop for BatchModule {
     fn drop(&mut self) {
-        self.output_file.write("#EOF\n".as_bytes()).expect(format!("Unable to write in file {}", self.filename).as_str());
+        if let Err(e) = self.output_file.write_all("#EOF\n".as_bytes()).and_then(|_| self.output_file.flush()) {
+            eprintln!("!!! Unable to write in file {} : {}", self.filename, e);
+        }
     }
 }
 
 impl BatchModule {
-      pub fn new() -> BatchModule {
+      pub fn new(offline_reference: bool) -> BatchModule {
             let filename = "batch.zsh";
             let file = File::create(&filename).expect(format!("Unable to create file {}", filename).as_str());
             let mut buf = BufWriter::new(file);
             buf.write("#!/bin/bash\n\n".as_bytes()).expect(format!("Unable to write in file {}", filename).as_str());
-            BatchModule { filename: filename.to_string(), output_file: buf}
+            BatchModule { filename: filename.to_string(), output_file: buf, offline_reference }
       }
 }
 
@@ -73,6 +146,7 @@ mod test {
     use std::fs;
     use std::io::Read;
 
+
         #[test]
     fn check_batch_module() {
           let ref_name = "ref.zsh";
@@ -80,6 +154,8 @@ mod test {
 
 # Doublon first_file <-> second_file
 
+# Hardlinked first_link <-> second_link
+
 if [ ! -L original -a -f original ]
 then
     rm duplicated
@@ -93,9 +169,10 @@ fi
 #EOF
 "#;
           {
-                let mut my_module = BatchModule::new();
-                my_module.treat_internal_doublon("first_file", "second_file");
-                let _ = my_module.treat_duplicated("original", "duplicated");
+                let mut my_module = BatchModule::new(false);
+                my_module.treat_internal_doublon(&FileTreeInfo::for_test("first_file", "", 0, 0, 0), &FileTreeInfo::for_test("second_file", "", 0, 0, 0));
+                my_module.treat_hardlinked(&FileTreeInfo::for_test("first_link", "", 0, 0, 0), &FileTreeInfo::for_test("second_link", "", 0, 0, 0));
+                let _ = my_module.treat_duplicated(&FileTreeInfo::for_test("original", "", 0, 0, 0), &FileTreeInfo::for_test("duplicated", "", 0, 0, 0));
 
                 // Dump ref file to make diff easier in case of mismatch
                 let file = File::create(&ref_name).expect(format!("Unable to create file {}", ref_name).as_str());
@@ -110,4 +187,84 @@ fi
           assert!(fs::remove_file(batch_name).is_ok());
           assert!(fs::remove_file(ref_name).is_ok());
     }
+
+    #[test]
+    fn check_offline_duplicated() {
+          let name = "offline.zsh";
+          {
+                let mut buf = BufWriter::new(File::create(name).expect("Unable to create file"));
+                dump_offline_duplicated(&mut buf, "/archive/original", "duplicated", false).expect("Error during write");
+                dump_offline_duplicated(&mut buf, "/archive/dir", "copy", true).expect("Error during write");
+          }
+          let contents = fs::read_to_string(name).expect("Unable to read file");
+          assert_eq!("# /archive/original is in offline reference\nif [ -e duplicated ]\nthen\n    rm duplicated\nfi\n\n\
+                      # /archive/dir is in offline reference\n# copy not removed : content of offline reference directory can not be checked\n\n", contents);
+          assert!(fs::remove_file(name).is_ok());
+    }
+
+    #[test]
+    fn check_duplicated_directory() {
+          let root = "batch_directory28";
+          let original = format!("{}/original", root);
+          let copy_name = format!("{}/copy", root);
+          assert!(fs::create_dir_all(format!("{}/sub", original)).is_ok());
+          assert!(fs::create_dir_all(&copy_name).is_ok());
+          assert!(fs::write(format!("{}/sub/a", original), "content").is_ok());
+          assert!(fs::write(format!("{}/b", copy_name), "content").is_ok());
+          assert!(std::os::unix::fs::symlink("target", format!("{}/l", original)).is_ok());
+          assert!(std::os::unix::fs::symlink("target", format!("{}/m", copy_name)).is_ok());
+          let name = "directory.zsh";
+          let copy = FileTreeInfo::for_test(&copy_name, "", 1, 2, 7);
+          {
+                let mut buf = BufWriter::new(File::create(name).expect("Unable to create file"));
+                dump_duplicated_directory(&mut buf, &original, &copy).expect("Error during write");
+          }
+          let contents = fs::read_to_string(name).expect("Unable to read file");
+          let reference = r#"# batch_directory28/copy : 2 items, 7 bytes
+if [ ! -L batch_directory28/original -a -d batch_directory28/original ]
+then
+    if [ $(find batch_directory28/copy ! -type d -printf x | wc -c) -eq 2 ] \
+        && [ ! -L batch_directory28/copy/b ] && cmp -s batch_directory28/original/sub/a batch_directory28/copy/b \
+        && [ -L batch_directory28/copy/m ] && [ "$(readlink batch_directory28/original/l)" = "$(readlink batch_directory28/copy/m)" ]
+    then
+        rm -r batch_directory28/copy
+    else
+        echo "batch_directory28/copy" differs from "batch_directory28/original"
+    fi
+elif [ -L batch_directory28/original  ]
+then
+    echo "batch_directory28/original" is a link
+else
+    echo "batch_directory28/original" do not exist
+fi
+
+"#;
+          assert_eq!(reference, contents);
+
+          // Content changed after generation of script
+          assert!(fs::write(format!("{}/b", copy_name), "changed").is_ok());
+          assert!(std::process::Command::new("bash").arg(name).status().is_ok_and(|s| s.success()));
+          assert!(fs::exists(&copy_name).is_ok_and(|e| e));
+          // Added file
+          assert!(fs::write(format!("{}/b", copy_name), "content").is_ok());
+          assert!(fs::write(format!("{}/c", copy_name), "content").is_ok());
+          assert!(std::process::Command::new("bash").arg(name).status().is_ok_and(|s| s.success()));
+          assert!(fs::exists(&copy_name).is_ok_and(|e| e));
+          assert!(fs::remove_file(format!("{}/c", copy_name)).is_ok());
+          assert!(std::process::Command::new("bash").arg(name).status().is_ok_and(|s| s.success()));
+          assert!(fs::exists(&copy_name).is_ok_and(|e| !e));
+
+          // Entry without copy in reference
+          assert!(fs::create_dir_all(&copy_name).is_ok());
+          assert!(fs::write(format!("{}/d", copy_name), "other").is_ok());
+          {
+                let mut buf = BufWriter::new(File::create(name).expect("Unable to create file"));
+                dump_duplicated_directory(&mut buf, &original, &copy).expect("Error during write");
+          }
+          let contents = fs::read_to_string(name).expect("Unable to read file");
+          assert!(contents.contains("# batch_directory28/copy not removed : batch_directory28/copy/d has no copy in batch_directory28/original\n"));
+          assert!(!contents.contains("rm -r"));
+          assert!(fs::remove_file(name).is_ok());
+          assert!(fs::remove_dir_all(root).is_ok());
+    }
 }
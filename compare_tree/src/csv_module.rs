@@ -0,0 +1,329 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::fs;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path};
+
+use crate::output_module::OutputModule;
+use crate::ct_utils::format_time;
+use crate::filetree_info::FileTreeInfo;
+use crate::scan_options::ScanOptions;
+use crate::sha1;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CsvColumn {
+    /// duplicate between trees or internal to other tree
+    Type,
+    Kind,
+    Reference,
+    Other,
+    Size,
+    ReferenceMtime,
+    OtherMtime,
+    Sha1,
+    /// Left empty, filled during review
+    Decision
+}
+
+pub const ALL_COLUMNS: [CsvColumn; 9] = [CsvColumn::Type, CsvColumn::Kind, CsvColumn::Reference, CsvColumn::Other, CsvColumn::Size,
+                                         CsvColumn::ReferenceMtime, CsvColumn::OtherMtime, CsvColumn::Sha1, CsvColumn::Decision];
+
+impl CsvColumn {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::Type => "type",
+            CsvColumn::Kind => "kind",
+            CsvColumn::Reference => "reference",
+            CsvColumn::Other => "other",
+            CsvColumn::Size => "size",
+            CsvColumn::ReferenceMtime => "reference_mtime",
+            CsvColumn::OtherMtime => "other_mtime",
+            CsvColumn::Sha1 => "sha1",
+            CsvColumn::Decision => "decision"
+        }
+    }
+
+    pub fn from_name(v: &str) -> Result<CsvColumn, String> {
+        match ALL_COLUMNS.iter().find(|c| c.name() == v) {
+            Some(column) => Ok(*column),
+            None => Err(format!("Unknown CSV column {}", v))
+        }
+    }
+}
+
+/// Quote field when it contains a separator, a quote or a line break
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Split CSV content in rows of fields, quoted fields may contain separators, quotes and line breaks
+pub fn parse_csv(content: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = content.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => { chars.next(); field.push('"'); },
+                '"' => quoted = false,
+                c => field.push(c)
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => quoted = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {},
+            '\n' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            },
+            c => field.push(c)
+        }
+    }
+    if quoted {
+        return Err(String::from("Unterminated quoted field"));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+pub struct CsvModule {
+      filename: String,
+      output_file: BufWriter<File>,
+      columns: Vec<CsvColumn>
+}
+
+fn mtime(name: &str) -> String {
+      match fs::symlink_metadata(name) {
+            Ok(metadata) => format_time(metadata.mtime()),
+            Err(_) => String::new()
+      }
+}
+
+impl CsvModule {
+      pub fn new(filename: &str, columns: &[CsvColumn]) -> CsvModule {
+            let file = File::create(filename).unwrap_or_else(|e| panic!("Unable to create file {} : {}", filename, e));
+            let mut module = CsvModule { filename: filename.to_string(), output_file: BufWriter::new(file), columns: columns.to_vec() };
+            let header = columns.iter().map(|c| c.name()).collect::<Vec<&str>>().join(",");
+            module.write_line(header);
+            module
+      }
+
+      fn write_line(&mut self, line: String) {
+            self.output_file.write_all(format!("{}\n", line).as_bytes()).unwrap_or_else(|e| panic!("Unable to write in file {} : {}", self.filename, e));
+      }
+
+      fn write_row(&mut self, row_type: &str, reference: &FileTreeInfo, other: &FileTreeInfo) {
+            let fields = self.columns.iter().map(|column| match column {
+                  CsvColumn::Type => row_type.to_string(),
+                  CsvColumn::Kind => other.kind.name().to_string(),
+                  CsvColumn::Reference => csv_field(&reference.name),
+                  CsvColumn::Other => csv_field(&other.name),
+                  CsvColumn::Size => other.size.to_string(),
+                  CsvColumn::ReferenceMtime => mtime(&reference.name),
+                  CsvColumn::OtherMtime => mtime(&other.name),
+                  CsvColumn::Sha1 => other.sha1.to_string(),
+                  CsvColumn::Decision => String::new()
+            }).collect::<Vec<String>>();
+            self.write_line(fields.join(","));
+      }
+}
+
+impl OutputModule for CsvModule {
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            self.write_row("internal", first, second);
+      }
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            self.write_row("duplicate", reference, other);
+            Ok(true)
+      }
+      /// Removing an hardlink frees nothing so there is nothing to review
+      fn treat_hardlinked(&mut self, _first: &FileTreeInfo, _second: &FileTreeInfo) {
+      }
+}
+
+impl Drop for CsvModule {
+    fn drop(&mut self) {
+        if let Err(e) = self.output_file.flush() {
+            eprintln!("!!! Unable to write in file {} : {}", self.filename, e);
+        }
+    }
+}
+
+/// Other entry must be strictly inside other directory, even through links of its parent directories
+fn check_other_path(other: &str, other_root: &str) -> Result<(), String> {
+    let path = Path::new(other);
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(format!("{} contains ..", other));
+    }
+    let (parent, file_name) = match (path.parent(), path.file_name()) {
+        // Entry of current directory has an empty parent
+        (Some(parent), Some(file_name)) if parent.as_os_str().is_empty() => (Path::new("."), file_name),
+        (Some(parent), Some(file_name)) => (parent, file_name),
+        _ => return Err(format!("{} is not an entry of {}", other, other_root))
+    };
+    match (fs::canonicalize(parent), fs::canonicalize(other_root)) {
+        (Ok(parent), Ok(root)) if parent.join(file_name) == root => Err(format!("{} is the other directory itself", other)),
+        (Ok(parent), Ok(root)) if parent.starts_with(&root) => Ok(()),
+        (Ok(_), Ok(_)) => Err(format!("{} is not in {}", other, other_root)),
+        (Err(e), _) | (_, Err(e)) => Err(format!("Unable to get absolute path of {} : {}", other, e))
+    }
+}
+
+/// Read a reviewed CSV and return the pairs whose other entry was approved for removal.
+/// Other entries must be located in other directory. Both entries are hashed again and must
+/// still have the SHA1 reviewed, reference is only checked when it is available on this machine
+pub fn read_plan(filename: &str, reference_root: &str, other_root: &str, options: &ScanOptions, offline_reference: bool) -> Result<Vec<(FileTreeInfo, FileTreeInfo)>, String> {
+    let content = match fs::read_to_string(filename) {
+        Ok(content) => content,
+        Err(e) => return Err(format!("Unable to read file {} : {}", filename, e))
+    };
+    let rows = parse_csv(&content).map_err(|e| format!("Invalid CSV file {} : {}", filename, e))?;
+    let header = match rows.first() {
+        Some(header) => header,
+        None => return Err(format!("CSV file {} is empty", filename))
+    };
+    let index = |column: CsvColumn| header.iter().position(|name| name == column.name());
+    let (reference, other, sha1, decision) = match (index(CsvColumn::Reference), index(CsvColumn::Other), index(CsvColumn::Sha1), index(CsvColumn::Decision)) {
+        (Some(reference), Some(other), Some(sha1), Some(decision)) => (reference, other, sha1, decision),
+        _ => return Err(format!("CSV file {} needs reference, other, sha1 and decision columns", filename))
+    };
+    let mut result = Vec::new();
+    for (number, row) in rows.iter().enumerate().skip(1) {
+        let field = |index: usize| row.get(index).map(|v| v.trim()).unwrap_or_default();
+        let location = format!("row {} of {}", number + 1, filename);
+        match field(decision).to_lowercase().as_str() {
+            "remove" => {},
+            "" | "keep" => continue,
+            value => return Err(format!("Unknown decision '{}' in {}", value, location))
+        }
+        let reviewed = sha1::Sha1Key::from_string(field(sha1)).map_err(|e| format!("{} in {}", e, location))?;
+        let (reference_name, other_name) = (field(reference), field(other));
+        check_other_path(other_name, other_root).map_err(|e| format!("{} in {}", e, location))?;
+        let other_entry = crate::rehash_entry(other_root, other_name, options)?;
+        let reference_entry = if offline_reference {
+            FileTreeInfo { name: reference_name.to_string(), ..other_entry.clone() }
+        } else {
+            if fs::canonicalize(reference_name).ok() == fs::canonicalize(other_name).ok() {
+                return Err(format!("{} is its own reference in {}", other_name, location));
+            }
+            crate::rehash_entry(reference_root, reference_name, options)?
+        };
+        if other_entry.sha1 != reviewed || !reference_entry.equivalent(&other_entry) {
+            return Err(format!("{} and {} do not have the reviewed content anymore in {}", reference_name, other_name, location));
+        }
+        result.push((reference_entry, other_entry));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+
+    #[test]
+    fn check_parse_csv() {
+        assert_eq!(vec!(vec!("a".to_string(), "b,c".to_string(), "d\"e\nf".to_string()), vec!("".to_string(), "g".to_string())),
+                   parse_csv("a,\"b,c\",\"d\"\"e\nf\"\r\n,g").unwrap());
+        assert!(parse_csv("a,\"b").is_err());
+        let name = "a, \"quoted\"\nname";
+        assert_eq!(vec!(vec!(name.to_string())), parse_csv(&csv_field(name)).unwrap());
+    }
+
+    #[test]
+    fn check_csv_module() {
+        let name = "review.csv";
+        {
+            let mut module = CsvModule::new(name, &[CsvColumn::Type, CsvColumn::Reference, CsvColumn::Other, CsvColumn::Size, CsvColumn::Decision]);
+            module.treat_internal_doublon(&FileTreeInfo::for_test("oth/a", "content", 0, 0, 3), &FileTreeInfo::for_test("oth/b,c", "content", 0, 0, 3));
+            module.treat_hardlinked(&FileTreeInfo::for_test("oth/d", "content", 0, 0, 3), &FileTreeInfo::for_test("oth/e", "content", 0, 0, 3));
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("ref/a", "content", 0, 0, 5), &FileTreeInfo::for_test("oth/\"f\"", "content", 0, 0, 5)).unwrap());
+        }
+        let contents = fs::read_to_string(name).expect("Unable to read CSV");
+        assert_eq!("type,reference,other,size,decision\ninternal,oth/a,\"oth/b,c\",3,\nduplicate,ref/a,\"oth/\"\"f\"\"\",5,\n", contents);
+        assert!(fs::remove_file(name).is_ok());
+    }
+
+    #[test]
+    fn check_read_plan() {
+        let root = Path::new("plan27");
+        fs::create_dir_all(root.join("ref")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth")).expect("Unable to create directory");
+        for (name, content) in [("ref/a", "Same"), ("ref/b", "Diff"), ("oth/\"f\"", "Same")] {
+            fs::write(root.join(name), content).expect("Unable to write file");
+        }
+        let sha1 = crate::compute_file_sha1("plan27/ref/a").expect("Unable to hash file").to_string();
+        let name = "plan27/review.csv";
+        let plan = |reference: &str, other: &str, sha1: &str, decision: &str| {
+            let row = [reference, other, sha1, decision].map(csv_field).join(",");
+            fs::write(name, format!("reference,other,sha1,decision\n{}\n", row)).expect("Unable to write CSV");
+            read_plan(name, "plan27/ref", "plan27/oth", &ScanOptions::default(), false)
+        };
+        let result = plan("plan27/ref/a", "plan27/oth/\"f\"", &sha1, "remove").expect("Error when reading plan");
+        assert_eq!(1, result.len());
+        assert_eq!(("plan27/ref/a", "plan27/oth/\"f\"", 4), (result[0].0.name.as_str(), result[0].1.name.as_str(), result[0].1.size));
+        assert!(plan("plan27/ref/a", "plan27/oth/\"f\"", &sha1, "keep").expect("Error when reading plan").is_empty());
+        assert!(plan("plan27/ref/a", "plan27/oth/\"f\"", &sha1, "maybe").is_err());
+        // Other entry out of other directory
+        assert!(plan("plan27/ref/a", "plan27/oth/../ref/a", &sha1, "remove").is_err());
+        assert!(plan("plan27/oth/\"f\"", "plan27/ref/a", &sha1, "remove").is_err());
+        assert_eq!(Err("plan27/oth is the other directory itself".to_string()), check_other_path("plan27/oth", "plan27/oth"));
+        assert_eq!(Err("plan27 is not in plan27/oth".to_string()), check_other_path("plan27", "plan27/oth"));
+        assert_eq!(Err("plan27 is the other directory itself".to_string()), check_other_path("plan27", "plan27"));
+        // Edited reference path or reviewed SHA1 no more matching content
+        assert!(plan("plan27/ref/b", "plan27/oth/\"f\"", &sha1, "remove").is_err());
+        let other_sha1 = crate::compute_file_sha1("plan27/ref/b").expect("Unable to hash file").to_string();
+        assert!(plan("plan27/ref/a", "plan27/oth/\"f\"", &other_sha1, "remove").is_err());
+        // With reference offline, other entry is checked against reviewed SHA1 only
+        fs::write(name, format!("reference,other,sha1,decision\n/archive/a,{},{},remove\n", csv_field("plan27/oth/\"f\""), sha1)).expect("Unable to write CSV");
+        assert_eq!(1, read_plan(name, "plan27/ref", "plan27/oth", &ScanOptions::default(), true).expect("Error when reading plan").len());
+        // Directories are hashed again with the anchored patterns and ignore files of their scan root
+        for (name, content) in [("ref/d/x", "Same"), ("ref/d/build/o", "Ref"), ("oth/d/x", "Same"), ("oth/d/build/o", "Oth")] {
+            fs::create_dir_all(root.join(name).parent().unwrap()).expect("Unable to create directory");
+            fs::write(root.join(name), content).expect("Unable to write file");
+        }
+        let dir_plan = |options: &ScanOptions| {
+            crate::analyse("plan27/oth", options).expect("Error during analyse");
+            let tree = crate::load_dump("plan27/oth").expect("Error when loading dump");
+            fs::remove_file(crate::dump_name("plan27/oth")).expect("Unable to remove dump");
+            let sha1 = tree.entries.iter().find(|e| e.name == "plan27/oth/d").expect("Directory not found").sha1.to_string();
+            fs::write(name, format!("reference,other,sha1,decision\nplan27/ref/d,plan27/oth/d,{},remove\n", sha1)).expect("Unable to write CSV");
+            read_plan(name, "plan27/ref", "plan27/oth", options, false)
+        };
+        assert!(dir_plan(&ScanOptions::default()).is_err());
+        assert_eq!(1, dir_plan(&ScanOptions { excludes: vec!("/d/build".to_string()), ..ScanOptions::default() }).expect("Error when reading plan").len());
+        fs::write("plan27/ref/.compare_tree_ignore", "build\n").expect("Unable to write ignore file");
+        fs::write("plan27/oth/.compare_tree_ignore", "build\n").expect("Unable to write ignore file");
+        assert_eq!(1, dir_plan(&ScanOptions::default()).expect("Error when reading plan").len());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+}
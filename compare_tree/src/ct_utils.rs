@@ -25,3 +25,124 @@ pub fn despecialise(name: &str) -> String {
 }
 
 
+pub fn escape_field(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+/// Reverse of escape_field
+pub fn unescape_field(field: &str) -> String {
+    let mut result = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\')
+        }
+    }
+    result
+}
+
+/// UTC date of a time given in seconds since epoch, as understood by spreadsheets
+pub fn format_time(seconds: i64) -> String {
+    let days = seconds.div_euclid(86400);
+    let time = seconds.rem_euclid(86400);
+    // Convert days since epoch to civil date, eras being 400 years long
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+/// Text to be inserted in HTML content or attribute
+pub fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
+
+/// Quoted JSON string
+pub fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c)
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Path of an entry relatively to the root of its scan, root itself being "."
+pub fn relative_name(root: &str, name: &str) -> String {
+    let root = root.trim_end_matches('/');
+    let relative = match name.strip_prefix(root) {
+        Some(relative) if root.is_empty() || relative.is_empty() || relative.starts_with('/') => relative,
+        _ => name
+    };
+    match relative.trim_start_matches('/') {
+        "" => String::from("."),
+        relative => relative.to_string()
+    }
+}
+
+/// Path of an entry given relatively to the current location of the root of its scan
+pub fn full_name(root: &str, relative: &str) -> String {
+    if relative == "." {
+        root.to_string()
+    } else {
+        format!("{}/{}", root.trim_end_matches('/'), relative)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_escape_field() {
+        assert_eq!("a\\tb\\nc\\\\d", escape_field("a\tb\nc\\d"));
+        assert_eq!("a\tb\nc\\d\\t", unescape_field(&escape_field("a\tb\nc\\d\\t")));
+    }
+
+    #[test]
+    fn check_format_time() {
+        assert_eq!("1970-01-01 00:00:00", format_time(0));
+        assert_eq!("2000-02-29 12:34:56", format_time(951827696));
+        assert_eq!("1969-12-31 23:59:59", format_time(-1));
+    }
+    #[test]
+    fn check_html_escape() {
+        assert_eq!("&lt;a href=&quot;x&quot;&gt;&amp;&#39;", html_escape("<a href=\"x\">&'"));
+    }
+    #[test]
+    fn check_json_string() {
+        assert_eq!(r#""a \"b\" c\\d\te\n\u0001""#, json_string("a \"b\" c\\d\te\n\u{1}"));
+    }
+    #[test]
+    fn check_relative_name() {
+        assert_eq!(".", relative_name("ref", "ref"));
+        assert_eq!(".", relative_name("ref/", "ref/"));
+        assert_eq!("a/b", relative_name("ref/", "ref/a/b"));
+        assert_eq!("a", relative_name("/", "/a"));
+        assert_eq!("ref2/a", relative_name("ref", "ref2/a"));
+        assert_eq!("ref/a/b", full_name("ref/", "a/b"));
+        assert_eq!("ref/", full_name("ref/", "."));
+        assert_eq!("/a", full_name("/", "a"));
+    }
+}
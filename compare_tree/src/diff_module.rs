@@ -0,0 +1,352 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
+
+use crate::ct_utils::escape_field;
+use crate::dump_tree::DumpTree;
+use crate::filetree_info::FileTreeInfo;
+use crate::sha1::Sha1Key;
+
+/// Entries present in both trees indexed by SHA1 : first reference entry and first other name
+pub type CommonEntries = HashMap<Sha1Key, (FileTreeInfo, String)>;
+
+pub fn find_common<'a>(common: &'a CommonEntries, entry: &FileTreeInfo) -> Option<&'a (FileTreeInfo, String)> {
+    common.get(&entry.sha1).filter(|(reference, _)| reference.equivalent(entry))
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DiffStatus {
+    OnlyInReference,
+    OnlyInOther,
+    InBoth,
+    // Snapshot statuses, reference being the old snapshot and other the new one
+    Unchanged,
+    Moved,
+    Modified,
+    Added,
+    Deleted
+}
+
+const NB_STATUS: usize = 8;
+
+impl DiffStatus {
+    fn tag(&self) -> &'static str {
+        match self {
+            DiffStatus::OnlyInReference => "only_reference",
+            DiffStatus::OnlyInOther => "only_other",
+            DiffStatus::InBoth => "both",
+            DiffStatus::Unchanged => "unchanged",
+            DiffStatus::Moved => "moved",
+            DiffStatus::Modified => "modified",
+            DiffStatus::Added => "added",
+            DiffStatus::Deleted => "deleted"
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            DiffStatus::OnlyInReference => "only in reference",
+            DiffStatus::OnlyInOther => "only in other",
+            DiffStatus::InBoth => "in both",
+            DiffStatus::Unchanged => "unchanged",
+            DiffStatus::Moved => "moved or renamed",
+            DiffStatus::Modified => "modified in place",
+            DiffStatus::Added => "added",
+            DiffStatus::Deleted => "deleted"
+        }
+    }
+
+    fn all() -> [DiffStatus; NB_STATUS] {
+        [DiffStatus::OnlyInReference, DiffStatus::OnlyInOther, DiffStatus::InBoth,
+         DiffStatus::Unchanged, DiffStatus::Moved, DiffStatus::Modified, DiffStatus::Added, DiffStatus::Deleted]
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DiffEntry {
+    pub status: DiffStatus,
+    pub reference: String,
+    pub other: String,
+    pub sha1: Sha1Key,
+    pub height: u32,
+    pub nb_item: u32
+}
+
+fn contains_common(tree: &DumpTree, common: &CommonEntries) -> Vec<bool> {
+    // Dump is written children first so children are always treated before their parent
+    let mut result = Vec::with_capacity(tree.entries.len());
+    for (index, entry) in tree.entries.iter().enumerate() {
+        let value = find_common(common, entry).is_some() || tree.children(index).iter().any(|c| result[*c]);
+        result.push(value);
+    }
+    result
+}
+
+/// Walk tree from its root and stop at the highest directory whose content is
+/// fully present in both trees or fully present in only one of them
+pub fn classify(tree: &DumpTree, common: &CommonEntries, is_reference: bool) -> Vec<DiffEntry> {
+    let contains_common = contains_common(tree, common);
+
+    let mut result = Vec::new();
+    let mut stack = vec!(tree.root());
+    while let Some(index) = stack.pop() {
+        if tree.is_empty_dir(index) {
+            continue;
+        }
+        let entry = &tree.entries[index];
+        if let Some((reference, _)) = find_common(common, entry) {
+            // Common entries are reported once, from other tree point of view
+            if !is_reference {
+                result.push(DiffEntry { status: DiffStatus::InBoth,
+                                        reference: reference.name.clone(),
+                                        other: entry.name.clone(),
+                                        sha1: entry.sha1.clone(),
+                                        height: entry.height,
+                                        nb_item: entry.nb_item });
+            }
+        }
+        else if !contains_common[index] {
+            let (status, reference, other) = if is_reference {
+                (DiffStatus::OnlyInReference, entry.name.clone(), String::new())
+            } else {
+                (DiffStatus::OnlyInOther, String::new(), entry.name.clone())
+            };
+            result.push(DiffEntry { status, reference, other, sha1: entry.sha1.clone(), height: entry.height, nb_item: entry.nb_item });
+        }
+        else {
+            stack.extend(tree.children(index).iter().rev());
+        }
+    }
+    result
+}
+
+fn snapshot_entry(status: DiffStatus, old: String, new: String, entry: &FileTreeInfo) -> DiffEntry {
+    DiffEntry { status, reference: old, other: new, sha1: entry.sha1.clone(), height: entry.height, nb_item: entry.nb_item }
+}
+
+fn mark_subtree(tree: &DumpTree, index: usize, marked: &mut HashSet<usize>) {
+    let mut stack = vec!(index);
+    while let Some(current) = stack.pop() {
+        marked.insert(current);
+        stack.extend(tree.children(current));
+    }
+}
+
+/// Compare two snapshots of the same tree using paths relative to their roots.
+/// A directory moved without modification is reported as a single event
+pub fn classify_snapshot(old: &DumpTree, new: &DumpTree, common: &CommonEntries) -> Vec<DiffEntry> {
+    let old_paths: HashMap<String, usize> = (0..old.entries.len()).map(|i| (old.relative_path(i), i)).collect();
+    let new_paths: HashMap<String, usize> = (0..new.entries.len()).map(|i| (new.relative_path(i), i)).collect();
+    // Only content present in both snapshots can have been moved
+    let mut old_by_sha1: HashMap<&Sha1Key, Vec<usize>> = HashMap::new();
+    for (index, entry) in old.entries.iter().enumerate() {
+        if find_common(common, entry).is_some() {
+            old_by_sha1.entry(&entry.sha1).or_default().push(index);
+        }
+    }
+    let still_in_place = |old_index: usize| {
+        match new_paths.get(&old.relative_path(old_index)) {
+            Some(new_index) => new.entries[*new_index].equivalent(&old.entries[old_index]),
+            None => false
+        }
+    };
+    let new_contains_common = contains_common(new, common);
+
+    let mut result = Vec::new();
+    let mut moved = HashSet::new();
+    let mut stack = vec!(new.root());
+    while let Some(index) = stack.pop() {
+        if new.is_empty_dir(index) {
+            continue;
+        }
+        let entry = &new.entries[index];
+        let path = new.relative_path(index);
+        let old_index = old_paths.get(&path).copied();
+        if let Some(old_index) = old_index.filter(|i| old.entries[*i].equivalent(entry)) {
+            result.push(snapshot_entry(DiffStatus::Unchanged, old.relative_path(old_index), path, entry));
+            continue;
+        }
+        let origin = old_by_sha1.get(&entry.sha1).and_then(|list| {
+            list.iter().copied().find(|i| old.entries[*i].equivalent(entry) && !moved.contains(i) && !still_in_place(*i))
+        });
+        if let Some(origin) = origin {
+            mark_subtree(old, origin, &mut moved);
+            result.push(snapshot_entry(DiffStatus::Moved, old.relative_path(origin), path, entry));
+        }
+        else if let Some(old_index) = old_index {
+            if entry.height > 0 && old.entries[old_index].height > 0 {
+                stack.extend(new.children(index).iter().rev());
+            }
+            else {
+                result.push(snapshot_entry(DiffStatus::Modified, path.clone(), path, entry));
+            }
+        }
+        else if entry.height > 0 && new_contains_common[index] {
+            stack.extend(new.children(index).iter().rev());
+        }
+        else {
+            result.push(snapshot_entry(DiffStatus::Added, String::new(), path, entry));
+        }
+    }
+
+    // Remaining content of old snapshot that was neither kept nor moved has been deleted
+    let mut old_contains_moved = Vec::with_capacity(old.entries.len());
+    for index in 0..old.entries.len() {
+        let value = moved.contains(&index) || old.children(index).iter().any(|c| old_contains_moved[*c]);
+        old_contains_moved.push(value);
+    }
+    let mut stack = vec!(old.root());
+    while let Some(index) = stack.pop() {
+        if old.is_empty_dir(index) || moved.contains(&index) {
+            continue;
+        }
+        let entry = &old.entries[index];
+        let path = old.relative_path(index);
+        match new_paths.get(&path) {
+            Some(new_index) => {
+                let new_entry = &new.entries[*new_index];
+                if !new_entry.equivalent(entry) && entry.height > 0 && new_entry.height > 0 {
+                    stack.extend(old.children(index).iter().rev());
+                }
+            },
+            None => {
+                if old_contains_moved[index] {
+                    stack.extend(old.children(index).iter().rev());
+                }
+                else {
+                    result.push(snapshot_entry(DiffStatus::Deleted, path, String::new(), entry));
+                }
+            }
+        }
+    }
+    result
+}
+
+pub struct DiffModule {
+    filename: String,
+    output_file: BufWriter<File>,
+    counts: [u32; NB_STATUS]
+}
+
+impl DiffModule {
+    pub fn new(filename: &str) -> Result<DiffModule, String> {
+        let file = match File::create(filename) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Unable to create file {} : {}", filename, e))
+        };
+        let mut output_file = BufWriter::new(file);
+        if let Err(e) = output_file.write_all(b"status\treference\tother\tsha1\theight\tnb_item\n") {
+            return Err(format!("Unable to write in file {} : {}", filename, e));
+        }
+        Ok(DiffModule { filename: filename.to_string(), output_file, counts: [0; NB_STATUS] })
+    }
+
+    pub fn treat_entry(&mut self, entry: &DiffEntry) -> Result<(), String> {
+        match entry.status {
+            DiffStatus::OnlyInReference => eprintln!("<<< {}", entry.reference),
+            DiffStatus::OnlyInOther => eprintln!(">>> {}", entry.other),
+            DiffStatus::InBoth => eprintln!("=== {} <-> {}", entry.reference, entry.other),
+            DiffStatus::Unchanged => eprintln!("    {}", entry.other),
+            DiffStatus::Moved => eprintln!("R   {} -> {}", entry.reference, entry.other),
+            DiffStatus::Modified => eprintln!("M   {}", entry.other),
+            DiffStatus::Added => eprintln!("A   {}", entry.other),
+            DiffStatus::Deleted => eprintln!("D   {}", entry.reference)
+        }
+        self.counts[entry.status as usize] += 1;
+        let line = format!("{}\t{}\t{}\t{}\t{}\t{}\n",
+                           entry.status.tag(),
+                           escape_field(&entry.reference),
+                           escape_field(&entry.other),
+                           entry.sha1,
+                           entry.height,
+                           entry.nb_item);
+        match self.output_file.write_all(line.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Unable to write in file {} : {}", self.filename, e))
+        }
+    }
+
+    pub fn summary(&mut self) -> Result<(), String> {
+        let summary = DiffStatus::all().iter()
+                                       .filter(|status| self.counts[**status as usize] != 0)
+                                       .map(|status| format!("{} {}", self.counts[*status as usize], status.label()))
+                                       .collect::<Vec<String>>();
+        eprintln!("==> {}", if summary.is_empty() { String::from("No difference") } else { summary.join(", ") });
+        match self.output_file.flush() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("Unable to write in file {} : {}", self.filename, e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sha1;
+    use std::fs;
+
+
+    #[test]
+    fn check_classify() {
+        let reference = DumpTree::from_entries(vec!(FileTreeInfo::for_test("ref/dir/a", "a", 0, 0, 0),
+                                                    FileTreeInfo::for_test("ref/dir/b", "b", 0, 0, 0),
+                                                    FileTreeInfo::for_test("ref/dir", "dir", 1, 2, 0),
+                                                    FileTreeInfo::for_test("ref/only/c", "c", 0, 0, 0),
+                                                    FileTreeInfo::for_test("ref/only", "only", 1, 1, 0),
+                                                    FileTreeInfo::for_test("ref", "ref", 2, 3, 0)));
+        let other = DumpTree::from_entries(vec!(FileTreeInfo::for_test("oth/copy/a", "a", 0, 0, 0),
+                                                FileTreeInfo::for_test("oth/copy/b", "b", 0, 0, 0),
+                                                FileTreeInfo::for_test("oth/copy", "dir", 1, 2, 0),
+                                                FileTreeInfo::for_test("oth/d", "d", 0, 0, 0),
+                                                FileTreeInfo::for_test("oth", "oth", 2, 3, 0)));
+        let mut common = CommonEntries::new();
+        common.insert(reference.entries[2].sha1.clone(), (FileTreeInfo::for_test("ref/dir", "dir", 1, 2, 0), "oth/copy".to_string()));
+        common.insert(reference.entries[0].sha1.clone(), (FileTreeInfo::for_test("ref/dir/a", "a", 0, 0, 0), "oth/copy/a".to_string()));
+        common.insert(reference.entries[1].sha1.clone(), (FileTreeInfo::for_test("ref/dir/b", "b", 0, 0, 0), "oth/copy/b".to_string()));
+
+        let ref_result = classify(&reference, &common, true);
+        assert_eq!(vec!(("ref/only".to_string(), DiffStatus::OnlyInReference)),
+                   ref_result.iter().map(|e| (e.reference.clone(), e.status)).collect::<Vec<_>>());
+        let oth_result = classify(&other, &common, false);
+        assert_eq!(vec!((String::from("ref/dir"), String::from("oth/copy"), DiffStatus::InBoth),
+                        (String::new(), String::from("oth/d"), DiffStatus::OnlyInOther)),
+                   oth_result.iter().map(|e| (e.reference.clone(), e.other.clone(), e.status)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn check_diff_module() {
+        let filename = "diff_module_test.tsv";
+        {
+            let mut module = DiffModule::new(filename).expect("Unable to create diff module");
+            module.treat_entry(&DiffEntry { status: DiffStatus::OnlyInOther,
+                                            reference: String::new(),
+                                            other: "oth/with\ttab".to_string(),
+                                            sha1: sha1::compute_sha1(vec!(0)),
+                                            height: 0,
+                                            nb_item: 0 }).expect("Unable to treat entry");
+            module.summary().expect("Unable to write summary");
+        }
+        let content = fs::read_to_string(filename).expect("Unable to read diff report");
+        assert_eq!("status\treference\tother\tsha1\theight\tnb_item\nonly_other\t\toth/with\\ttab\t5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F\t0\t0\n", content);
+        assert!(fs::remove_file(filename).is_ok());
+    }
+}
@@ -17,16 +17,20 @@
 
 use crate::output_module::OutputModule;
 use crate::ct_utils::despecialise;
+use crate::filetree_info::FileTreeInfo;
 
 pub struct DisplayModule {
 }
 
 impl OutputModule for DisplayModule {
-      fn treat_internal_doublon(&mut self, first: &str, second: &str) {
-            eprintln!("!!! Doublon {} <-> {}", despecialise(first), despecialise(second));
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            eprintln!("!!! Doublon {} <-> {}", despecialise(&first.name), despecialise(&second.name));
       }
-      fn treat_duplicated(&mut self, reference: &str, other: &str) -> Result<bool, String> {
-            eprintln!("{} TO REMOVE {}", reference, despecialise(other));
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            eprintln!("{} TO REMOVE {}", reference.name, despecialise(&other.name));
             Ok(true)
       }
+      fn treat_hardlinked(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            eprintln!("=== Hardlinked {} <-> {}", despecialise(&first.name), despecialise(&second.name));
+      }
 }
@@ -0,0 +1,139 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::ct_utils::{full_name, relative_name};
+use crate::filetree_info::FileTreeInfo;
+
+/// In memory view of a dump, giving access to the children of each directory.
+/// Dumps are written children first so the root is always the last entry.
+pub struct DumpTree {
+    pub entries: Vec<FileTreeInfo>,
+    children: HashMap<String, Vec<usize>>,
+    parents: Vec<Option<usize>>
+}
+
+impl DumpTree {
+    /// Load a dump whose paths are relative to its root, giving them the current location of this root
+    pub fn load(filename: &str, root: &str) -> Result<DumpTree, String> {
+        let file = match File::open(filename) {
+            Ok(f) => f,
+            Err(e) => return Err(format!("Unable to open file {} {}", filename, e))
+        };
+        let reader = BufReader::new(file);
+        let mut entries = Vec::new();
+        for line_result in reader.lines() {
+            let line = match line_result {
+                Ok(l) => l,
+                Err(e) => return Err(format!("Unable to read from {} : {}", filename, e))
+            };
+            // Skip dump header
+            if line.starts_with('#') {
+                continue;
+            }
+            let entry = FileTreeInfo::from(&line)?;
+            entries.push(FileTreeInfo { name: full_name(root, &entry.name), ..entry });
+        }
+        if entries.is_empty() {
+            return Err(format!("Dump {} is empty", filename));
+        }
+        Ok(DumpTree::from_entries(entries))
+    }
+
+    pub fn from_entries(entries: Vec<FileTreeInfo>) -> DumpTree {
+        let mut children: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, entry) in entries.iter().enumerate() {
+            if let Some(parent) = Path::new(&entry.name).parent().and_then(|p| p.to_str()) {
+                children.entry(parent.to_string()).or_default().push(index);
+            }
+        }
+        let mut tree = DumpTree { entries, children, parents: Vec::new() };
+        tree.parents = vec!(None; tree.entries.len());
+        for index in 0..tree.entries.len() {
+            for child in tree.children(index).to_vec() {
+                tree.parents[child] = Some(index);
+            }
+        }
+        tree
+    }
+
+    pub fn parent(&self, index: usize) -> Option<usize> {
+        self.parents[index]
+    }
+
+    /// Entry itself followed by its ancestors up to the root
+    pub fn ancestors(&self, index: usize) -> Vec<usize> {
+        let mut result = vec!(index);
+        while let Some(parent) = self.parent(result[result.len() - 1]) {
+            result.push(parent);
+        }
+        result
+    }
+
+    pub fn root(&self) -> usize {
+        self.entries.len() - 1
+    }
+
+    pub fn children(&self, index: usize) -> &[usize] {
+        let name = &self.entries[index].name;
+        // Path given on command line may end with a separator
+        let key = if name.len() > 1 { name.trim_end_matches('/') } else { name };
+        match self.children.get(key) {
+            Some(list) => list,
+            None => &[]
+        }
+    }
+
+    /// Path of entry relatively to the root of its tree, root itself being "."
+    pub fn relative_path(&self, index: usize) -> String {
+        relative_name(&self.entries[self.root()].name, &self.entries[index].name)
+    }
+
+    /// Empty directories are not taken into account when hashing their parent
+    pub fn is_empty_dir(&self, index: usize) -> bool {
+        let entry = &self.entries[index];
+        entry.height > 0 && entry.nb_item == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+
+    #[test]
+    fn check_dump_tree_children() {
+        let tree = DumpTree::from_entries(vec!(FileTreeInfo::for_test("root/dir/a", "root/dir/a", 0, 0, 0),
+                                               FileTreeInfo::for_test("root/dir", "root/dir", 1, 1, 0),
+                                               FileTreeInfo::for_test("root/b", "root/b", 0, 0, 0),
+                                               FileTreeInfo::for_test("root/empty", "root/empty", 1, 0, 0),
+                                               FileTreeInfo::for_test("root", "root", 2, 2, 0)));
+        assert_eq!(4, tree.root());
+        assert_eq!(&[1, 2, 3], tree.children(tree.root()));
+        assert_eq!(&[0], tree.children(1));
+        assert!(tree.children(0).is_empty());
+        assert_eq!(vec!(0, 1, 4), tree.ancestors(0));
+        assert_eq!(None, tree.parent(4));
+        assert!(tree.is_empty_dir(3));
+        assert!(!tree.is_empty_dir(0));
+    }
+}
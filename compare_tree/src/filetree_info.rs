@@ -19,18 +19,130 @@ use std::fmt;
 use crate::sha1;
 use std::str::FromStr;
 
+/// Type of a tree entry. Each type has its own hash domain so that entries
+/// of different types can never be considered as equivalent
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy)]
+pub enum EntryKind {
+    File,
+    Link,
+    Directory,
+    /// FIFO, socket or device node
+    Special,
+    /// Entry that could not be read
+    Error,
+    /// Directory containing unreadable or unstable entries
+    Incomplete,
+    /// File modified while being hashed
+    Unstable
+}
+
+impl EntryKind {
+    pub fn tag(&self) -> char {
+        match self {
+            EntryKind::File => 'f',
+            EntryKind::Link => 'l',
+            EntryKind::Directory => 'd',
+            EntryKind::Special => 's',
+            EntryKind::Error => 'e',
+            EntryKind::Incomplete => 'i',
+            EntryKind::Unstable => 'u'
+        }
+    }
+
+    pub fn from_tag(v: &str) -> Result<EntryKind, String> {
+        match v {
+            "f" => Ok(EntryKind::File),
+            "l" => Ok(EntryKind::Link),
+            "d" => Ok(EntryKind::Directory),
+            "s" => Ok(EntryKind::Special),
+            "e" => Ok(EntryKind::Error),
+            "i" => Ok(EntryKind::Incomplete),
+            "u" => Ok(EntryKind::Unstable),
+            _ => Err(format!("Unknown entry kind {}", v))
+        }
+    }
+
+    /// Name used in reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Link => "link",
+            EntryKind::Directory => "dir",
+            EntryKind::Special => "special",
+            EntryKind::Error => "error",
+            EntryKind::Incomplete => "incomplete",
+            EntryKind::Unstable => "unstable"
+        }
+    }
+
+    /// Prefix of hashed data
+    pub fn domain(&self) -> &'static [u8] {
+        match self {
+            EntryKind::File => b"file\0",
+            EntryKind::Link => b"link\0",
+            EntryKind::Directory => b"dir\0",
+            EntryKind::Special => b"special\0",
+            EntryKind::Error => b"error\0",
+            EntryKind::Incomplete => b"incomplete\0",
+            EntryKind::Unstable => b"unstable\0"
+        }
+    }
+}
+
+/// Identity of the inode of a file, shared by all its hardlinks
+#[derive(Debug, PartialEq, PartialOrd, Eq, Ord, Clone, Copy, Hash)]
+pub struct Inode {
+    pub dev: u64,
+    pub ino: u64,
+    /// Number of hardlinks to this inode
+    pub nlink: u64
+}
+
+impl fmt::Display for Inode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.dev, self.ino, self.nlink)
+    }
+}
+
+impl Inode {
+    /// "-" stands for entries without inode information
+    pub fn from(v: &str) -> Result<Option<Inode>, String> {
+        if v == "-" {
+            return Ok(None);
+        }
+        let values = v.split(':').map(u64::from_str).collect::<Result<Vec<u64>, _>>();
+        match values.as_deref() {
+            Ok([dev, ino, nlink]) => Ok(Some(Inode { dev: *dev, ino: *ino, nlink: *nlink })),
+            _ => Err(format!("Filetree_info.inode : invalid value {}", v))
+        }
+    }
+
+    pub fn same_file(&self, op2: &Inode) -> bool {
+        self.dev == op2.dev && self.ino == op2.ino
+    }
+}
+
 #[derive(Debug)]
-#[derive(PartialEq, PartialOrd, Eq, Ord)]
+#[derive(PartialEq, PartialOrd, Eq, Ord, Clone)]
 pub struct FileTreeInfo {
     pub sha1: sha1::Sha1Key,
+    pub kind: EntryKind,
     pub height: u32,
     pub nb_item: u32,
-    pub name: String
+    /// Size in bytes, sum of the sizes of included files for a directory
+    pub size: u64,
+    pub name: String,
+    /// Only known for files. Kept after name so that entries with same SHA1 are sorted by name
+    pub inode: Option<Inode>
 }
 
 impl fmt::Display for FileTreeInfo {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {}, {}, {}", self.sha1, self.name, self.height, self.nb_item)
+        let inode = match &self.inode {
+            Some(inode) => inode.to_string(),
+            None => "-".to_string()
+        };
+        write!(f, "{} {}, {}, {}, {}, {}, {}", self.sha1, self.name, self.height, self.nb_item, self.kind.tag(), self.size, inode)
     }
 }
 
@@ -42,39 +154,74 @@ impl FileTreeInfo {
             None => return Err(format!("Space not found in {}", v))
         };
 
-        let last_comma_pos_result = v.rfind(", ");
-        let last_comma_pos = match last_comma_pos_result {
-            Some(i) => i,
+        // Name can contain ", " so fields are extracted from the end of the line
+        let mut fields = v[space_pos + 1..].rsplitn(6, ", ");
+        let inode = Inode::from(fields.next().unwrap_or_default())?;
+        let size_slice = match fields.next() {
+            Some(slice) => slice,
+            None => return Err(format!("',' following size not found in {}", v))
+        };
+        let size = match u64::from_str(size_slice) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Filetree_info.size : Error {} when converting {} to u64", e, size_slice))
+        };
+        let kind_slice = match fields.next() {
+            Some(slice) => slice,
+            None => return Err(format!("',' following kind not found in {}", v))
+        };
+        let kind = EntryKind::from_tag(kind_slice)?;
+        let nb_item_slice = match fields.next() {
+            Some(slice) => slice,
             None => return Err(format!("Last ',' not found in {}", v))
         };
-        let nb_item_slice = &v[last_comma_pos + 2..];
         let nb_item_result = u32::from_str(nb_item_slice);
         let nb_item = match nb_item_result {
             Ok(v) => v,
             Err(e) => return Err(format!("Filetree_info.nb_item : Error {} when converting {} to u32", e, nb_item_slice))
         };
-        let height_comma_pos_result = v[0..last_comma_pos - 1].rfind(", ");
-        let height_comma_pos = match height_comma_pos_result {
-            Some(i) => i,
-            None => return Err(format!("',' following name not found in {}", v))
+        let height_slice = match fields.next() {
+            Some(slice) => slice,
+            None => return Err(format!("',' following height not found in {}", v))
         };
-        let height_slice = &v[height_comma_pos + 2..last_comma_pos];
         let height_result = u32::from_str(height_slice);
         let height = match height_result {
             Ok(v) => v,
-            Err(e) => return Err(format!("Filetree_info.height : Error {} when converting {} to u32 {} {}", e, height_slice, height_comma_pos, last_comma_pos))
+            Err(e) => return Err(format!("Filetree_info.height : Error {} when converting {} to u32", e, height_slice))
+        };
+        let name = match fields.next() {
+            Some(slice) => slice,
+            None => return Err(format!("',' following name not found in {}", v))
         };
         let result = FileTreeInfo {
-            name: v[space_pos + 1..height_comma_pos].to_string(),
-            height: height,
+            name: name.to_string(),
+            kind,
+            height,
             sha1: sha1::Sha1Key::from_string(&v[0..space_pos])?,
-            nb_item: nb_item
+            nb_item,
+            size,
+            inode
         };
         Ok(result)
 
     }
+    /// Entries are hardlinks to the same inode
+    pub fn same_inode(&self, op2: &FileTreeInfo) -> bool {
+        match (&self.inode, &op2.inode) {
+            (Some(a), Some(b)) => a.same_file(b),
+            _ => false
+        }
+    }
+
+    /// Entries whose content is not fully known are never equivalent
     pub fn equivalent(&self, op2: &FileTreeInfo) -> bool {
-        self.height == op2.height && self.sha1 == op2.sha1 && self.nb_item == op2.nb_item
+        !matches!(self.kind, EntryKind::Error | EntryKind::Incomplete | EntryKind::Unstable) &&
+        self.kind == op2.kind && self.height == op2.height && self.sha1 == op2.sha1 && self.nb_item == op2.nb_item
+    }
+
+    /// Entry of tests, a file when height is null and a directory otherwise, whose SHA1 is the one of content
+    #[cfg(test)]
+    pub fn for_test(name: &str, content: &str, height: u32, nb_item: u32, size: u64) -> FileTreeInfo {
+        FileTreeInfo { name: name.to_string(), kind: if height == 0 { EntryKind::File } else { EntryKind::Directory }, height, nb_item, size, inode: None, sha1: sha1::compute_sha1(Vec::from(content)) }
     }
 
 }
@@ -87,9 +234,12 @@ mod test {
     fn create_filetree_info() {
         let ref_filetree_info = FileTreeInfo {
             name: "filetree".to_string(),
+            kind: EntryKind::Directory,
             height: 8,
             sha1: sha1::compute_sha1(vec!(0)),
-            nb_item: 10
+            nb_item: 10,
+            size: 0,
+            inode: None
         };
         assert_eq!(ref_filetree_info.name, "filetree");
         assert_eq!(ref_filetree_info.height, 8);
@@ -101,64 +251,91 @@ mod test {
     fn check_filetree_info_display() {
         let ref_filetree_info = FileTreeInfo {
             name: "filetree".to_string(),
+            kind: EntryKind::Directory,
             height: 8,
             sha1: sha1::compute_sha1(vec!(0)),
-            nb_item: 10
+            nb_item: 10,
+            size: 0,
+            inode: None
         };
-        assert_eq!(format!("{}", ref_filetree_info), "5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F filetree, 8, 10");
+        assert_eq!(format!("{}", ref_filetree_info), "5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F filetree, 8, 10, d, 0, -");
     }
     #[test]
     fn check_filetree_info_order() {
         let filetree_info1 = FileTreeInfo {
             name: "a".to_string(),
+            kind: EntryKind::Directory,
             height: 1,
             nb_item: 0,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(1))
         };
         let filetree_info2 = FileTreeInfo {
             name: "b".to_string(),
+            kind: EntryKind::Directory,
             height: 1,
             nb_item: 0,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(0))
         };
         print!("{:?}\n{:?}", filetree_info1, filetree_info2);
         assert!(filetree_info1 > filetree_info2);
         let filetree_info3 = FileTreeInfo {
             name: "z".to_string(),
+            kind: EntryKind::Directory,
             height: 1,
             nb_item: 0,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(0))
         };
         let filetree_info4 = FileTreeInfo {
             name: "b".to_string(),
+            kind: EntryKind::Directory,
             height: 2,
             nb_item: 0,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(0))
         };
         assert!(filetree_info3 < filetree_info4);
         let filetree_info5 = FileTreeInfo {
             name: "a".to_string(),
+            kind: EntryKind::Directory,
             height: 2,
             nb_item: 7,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(0))
         };
         let filetree_info6 = FileTreeInfo {
             name: "a".to_string(),
+            kind: EntryKind::Directory,
             height: 2,
             nb_item: 5,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(0))
         };
         assert!(filetree_info5 > filetree_info6);
         let filetree_info7 = FileTreeInfo {
             name: "b".to_string(),
+            kind: EntryKind::Directory,
             height: 2,
             nb_item: 7,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(0))
         };
         let filetree_info8 = FileTreeInfo {
             name: "a".to_string(),
+            kind: EntryKind::Directory,
             height: 2,
             nb_item: 7,
+            size: 0,
+            inode: None,
             sha1: sha1::compute_sha1(vec!(0))
         };
         assert!(filetree_info7 > filetree_info8);
@@ -167,10 +344,54 @@ mod test {
     fn check_from_string() {
         let ref_filetree_info = FileTreeInfo {
             name: "filetree".to_string(),
+            kind: EntryKind::Directory,
             height: 8,
             sha1: sha1::compute_sha1(vec!(0)),
-            nb_item: 10
+            nb_item: 10,
+            size: 0,
+            inode: None
+        };
+        assert_eq!( ref_filetree_info, FileTreeInfo::from("5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F filetree, 8, 10, d, 0, -").expect("Error during string conversion"));
+    }
+    #[test]
+    fn check_from_string_with_comma() {
+        let info = FileTreeInfo::from("5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F dir/a, b, 0, 0, l, 6, -").expect("Error during string conversion");
+        assert_eq!("dir/a, b", info.name);
+        assert_eq!(EntryKind::Link, info.kind);
+        assert_eq!(6, info.size);
+        assert!(FileTreeInfo::from("5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F dir/a, 0, 0, x, 0, -").is_err());
+    }
+    #[test]
+    fn check_from_string_with_inode() {
+        let info = FileTreeInfo::from("5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F dir/a, 0, 0, f, 6, 2049:1234:2").expect("Error during string conversion");
+        assert_eq!(Some(Inode { dev: 2049, ino: 1234, nlink: 2 }), info.inode);
+        assert_eq!("5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F dir/a, 0, 0, f, 6, 2049:1234:2", info.to_string());
+        let other = FileTreeInfo { name: "dir/b".to_string(), inode: Some(Inode { dev: 2049, ino: 1234, nlink: 2 }), ..info.clone() };
+        assert!(info.same_inode(&other));
+        assert!(!info.same_inode(&FileTreeInfo { inode: None, ..other }));
+        assert!(FileTreeInfo::from("5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F dir/a, 0, 0, f, 6, 2049:1234").is_err());
+    }
+    #[test]
+    fn check_equivalent_kind() {
+        let file = FileTreeInfo {
+            name: "file".to_string(),
+            kind: EntryKind::File,
+            height: 0,
+            sha1: sha1::compute_sha1(vec!(0)),
+            nb_item: 0,
+            size: 0,
+            inode: None
+        };
+        let link = FileTreeInfo {
+            name: "link".to_string(),
+            kind: EntryKind::Link,
+            height: 0,
+            sha1: sha1::compute_sha1(vec!(0)),
+            nb_item: 0,
+            size: 0,
+            inode: None
         };
-        assert_eq!( ref_filetree_info, FileTreeInfo::from("5BA93C9DB0CFF93F52B521D7420E43F6EDA2784F filetree, 8, 10").expect("Error during string conversion"));
+        assert!(file.equivalent(&file.clone()));
+        assert!(!file.equivalent(&link));
     }
 }
@@ -0,0 +1,342 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::scan_options::ScanOptions;
+use crate::filetree_info::EntryKind;
+use crate::filetree_info::FileTreeInfo;
+
+pub const IGNORE_FILE: &str = ".compare_tree_ignore";
+pub const GIT_IGNORE_FILE: &str = ".gitignore";
+
+/// Glob pattern following gitignore syntax
+#[derive(Debug, PartialEq)]
+pub struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    /// Pattern containing a separator is matched against the whole relative path,
+    /// otherwise it is matched against the name of the entry
+    anchored: bool
+}
+
+impl Pattern {
+    /// Return None for blank lines and comments
+    pub fn parse(line: &str) -> Option<Pattern> {
+        let mut text = line.trim_end_matches(['\n', '\r']);
+        // Trailing spaces are ignored unless escaped
+        while text.ends_with(' ') && !text.ends_with("\\ ") {
+            text = &text[..text.len() - 1];
+        }
+        if text.is_empty() || text.starts_with('#') {
+            return None;
+        }
+        let negated = text.starts_with('!');
+        if negated {
+            text = &text[1..];
+        }
+        let dir_only = text.ends_with('/');
+        let text = text.trim_end_matches('/');
+        let anchored = text.contains('/');
+        let text = text.trim_start_matches('/');
+        if text.is_empty() {
+            return None;
+        }
+        Some(Pattern { glob: text.chars().collect(), negated, dir_only, anchored })
+    }
+
+    /// Path is relative to the directory where pattern is defined
+    pub fn matches(&self, relative_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let text = if self.anchored {
+            relative_path
+        } else {
+            relative_path.rsplit('/').next().unwrap_or(relative_path)
+        };
+        glob_match(&self.glob, &text.chars().collect::<Vec<char>>())
+    }
+}
+
+/// Match text with a glob where '*' and '?' do not match separator and '**' matches
+/// any number of directories
+pub fn glob_match(glob: &[char], text: &[char]) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some('*') if glob.get(1) == Some(&'*') => {
+            let rest = &glob[2..];
+            if rest.is_empty() {
+                return true;
+            }
+            // "**/" also matches zero directory
+            if rest[0] == '/' && glob_match(&rest[1..], text) {
+                return true;
+            }
+            (0..text.len() + 1).any(|i| glob_match(rest, &text[i..]))
+        },
+        Some('*') => {
+            let rest = &glob[1..];
+            for i in 0..text.len() + 1 {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        },
+        Some('?') => !text.is_empty() && text[0] != '/' && glob_match(&glob[1..], &text[1..]),
+        Some('[') => match text.first() {
+            Some(c) if *c != '/' => match match_class(&glob[1..], *c) {
+                Some((matched, consumed)) => matched && glob_match(&glob[1 + consumed..], &text[1..]),
+                // Unterminated class is a literal '['
+                None => *c == '[' && glob_match(&glob[1..], &text[1..])
+            },
+            _ => false
+        },
+        Some('\\') if glob.len() > 1 => !text.is_empty() && text[0] == glob[1] && glob_match(&glob[2..], &text[1..]),
+        Some(c) => !text.is_empty() && text[0] == *c && glob_match(&glob[1..], &text[1..])
+    }
+}
+
+/// Match character against class content following '['. Return if character
+/// matches and number of glob characters consumed including ']'
+fn match_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let negated = matches!(class.first(), Some('!') | Some('^'));
+    let mut index = if negated { 1 } else { 0 };
+    let mut found = false;
+    let mut first = true;
+    while index < class.len() {
+        let current = class[index];
+        if current == ']' && !first {
+            return Some((found != negated, index + 1));
+        }
+        first = false;
+        if index + 2 < class.len() && class[index + 1] == '-' && class[index + 2] != ']' {
+            found |= current <= c && c <= class[index + 2];
+            index += 3;
+        }
+        else {
+            found |= current == c;
+            index += 1;
+        }
+    }
+    None
+}
+
+/// Patterns defined for a directory and its descendants
+struct Rules {
+    base: String,
+    patterns: Vec<Pattern>
+}
+
+fn relative_to<'a>(base: &str, path: &'a str) -> Option<&'a str> {
+    path.strip_prefix(base.trim_end_matches('/')).and_then(|p| p.strip_prefix('/'))
+}
+
+/// Decide which entries are excluded from scan. Rules of ignore files are stacked
+/// while going down in directories, last matching pattern wins
+pub struct Filter {
+    root: String,
+    ignore_files: Vec<&'static str>,
+    includes: Vec<Pattern>,
+    excluded_paths: Vec<PathBuf>,
+    stack: Vec<Rules>
+}
+
+impl Filter {
+    pub fn new(root: &str, options: &ScanOptions) -> Filter {
+        let excludes = options.excludes.iter().filter_map(|p| Pattern::parse(p)).collect();
+        let includes = options.includes.iter().filter_map(|p| Pattern::parse(p)).collect();
+        let mut ignore_files = vec!(IGNORE_FILE);
+        if options.gitignore {
+            ignore_files.push(GIT_IGNORE_FILE);
+        }
+        let excluded_paths = options.for_root(root).excluded_paths.iter().map(PathBuf::from).collect();
+        Filter { root: root.to_string(), ignore_files, includes, excluded_paths, stack: vec!(Rules { base: root.to_string(), patterns: excludes }) }
+    }
+
+    /// Load ignore files of directory
+    pub fn enter(&mut self, dir: &str) -> Result<(), String> {
+        let mut patterns = Vec::new();
+        for ignore_file in self.ignore_files.iter() {
+            let path = Path::new(dir).join(ignore_file);
+            if !path.is_file() {
+                continue;
+            }
+            let content = match fs::read_to_string(&path) {
+                Ok(c) => c,
+                Err(e) => return Err(format!("Unable to read ignore file {} : {}", path.display(), e))
+            };
+            patterns.extend(content.lines().filter_map(Pattern::parse));
+        }
+        self.stack.push(Rules { base: dir.to_string(), patterns });
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.stack.pop();
+    }
+
+    pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        if is_dir && !self.excluded_paths.is_empty() && let Ok(canonical) = fs::canonicalize(path) && self.excluded_paths.contains(&canonical) {
+            return true;
+        }
+        // Ignore files describe the tree rather than being part of it, unless a pattern includes them again
+        let mut excluded = !is_dir && Path::new(path).file_name().and_then(|n| n.to_str()).is_some_and(|n| self.ignore_files.contains(&n));
+        for rules in self.stack.iter() {
+            let relative = match relative_to(&rules.base, path) {
+                Some(r) => r,
+                None => continue
+            };
+            for pattern in rules.patterns.iter() {
+                if pattern.matches(relative, is_dir) {
+                    excluded = !pattern.negated;
+                }
+            }
+        }
+        // Directories are always visited to find included files
+        if !excluded && !is_dir && !self.includes.is_empty() {
+            let relative = relative_to(&self.root, path).unwrap_or(path);
+            excluded = !self.includes.iter().any(|p| p.matches(relative, false));
+        }
+        excluded
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum EmptyFilePolicy {
+    /// Empty files are reported like any other file
+    #[default]
+    Report,
+    /// Empty files are never reported as duplicated
+    Ignore
+}
+
+/// Files out of size limits are not reported as duplicated. They are still
+/// part of the hash of their directory so directory equivalence is not modified
+#[derive(Debug, PartialEq, Default)]
+pub struct SizeFilter {
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Empty files are only subject to this policy, whatever the size limits
+    pub empty_files: EmptyFilePolicy
+}
+
+impl SizeFilter {
+    pub fn accept(&self, entry: &FileTreeInfo) -> bool {
+        match entry.kind {
+            EntryKind::File => {},
+            // Special files have no content, they only take part in directory hashes
+            EntryKind::Special => return false,
+            // Content is not fully known so it can not be reported as duplicated
+            EntryKind::Error | EntryKind::Incomplete | EntryKind::Unstable => return false,
+            _ => return true
+        }
+        if entry.size == 0 {
+            return self.empty_files == EmptyFilePolicy::Report;
+        }
+        self.min_size.is_none_or(|min| entry.size >= min) && self.max_size.is_none_or(|max| entry.size <= max)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check(glob: &str, text: &str) -> bool {
+        glob_match(&glob.chars().collect::<Vec<char>>(), &text.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn check_glob_match() {
+        assert!(check("*.txt", "file.txt"));
+        assert!(!check("*.txt", "dir/file.txt"));
+        assert!(check("**/file.txt", "file.txt"));
+        assert!(check("**/file.txt", "a/b/file.txt"));
+        assert!(check("a/**/b", "a/x/y/b"));
+        assert!(check("a/**/b", "a/b"));
+        assert!(check("node_modules/**", "node_modules/a/b"));
+        assert!(check("file?.txt", "file1.txt"));
+        assert!(!check("file?.txt", "file/.txt"));
+        assert!(check("[a-c]x", "bx"));
+        assert!(!check("[!a-c]x", "bx"));
+        assert!(check("\\*", "*"));
+        assert!(!check("\\*", "a"));
+    }
+
+    #[test]
+    fn check_pattern() {
+        assert_eq!(None, Pattern::parse("# comment"));
+        assert_eq!(None, Pattern::parse("   "));
+        let pattern = Pattern::parse("build/").expect("Pattern expected");
+        assert!(pattern.matches("a/build", true));
+        assert!(!pattern.matches("a/build", false));
+        let pattern = Pattern::parse("/doc/*.txt").expect("Pattern expected");
+        assert!(pattern.matches("doc/a.txt", false));
+        assert!(!pattern.matches("sub/doc/a.txt", false));
+        let pattern = Pattern::parse("!keep.swp").expect("Pattern expected");
+        assert!(pattern.negated);
+    }
+
+    #[test]
+    fn check_size_filter() {
+        let file = |size| FileTreeInfo::for_test("file", "", 0, 0, size);
+        let filter = SizeFilter { min_size: Some(10), max_size: Some(100), empty_files: EmptyFilePolicy::Report };
+        assert!(filter.accept(&file(0)));
+        assert!(!filter.accept(&file(9)));
+        assert!(filter.accept(&file(10)));
+        assert!(filter.accept(&file(100)));
+        assert!(!filter.accept(&file(101)));
+        let filter = SizeFilter { empty_files: EmptyFilePolicy::Ignore, ..SizeFilter::default() };
+        assert!(!filter.accept(&file(0)));
+        assert!(filter.accept(&file(1)));
+        let directory = FileTreeInfo { kind: EntryKind::Directory, height: 1, ..file(0) };
+        assert!(filter.accept(&directory));
+        for kind in [EntryKind::Error, EntryKind::Incomplete, EntryKind::Unstable] {
+            assert!(!filter.accept(&FileTreeInfo { kind, ..file(1) }));
+        }
+    }
+
+    #[test]
+    fn check_filter() {
+        let options = ScanOptions { excludes: vec!("*.swp".to_string(), "!keep.swp".to_string(), ".git/".to_string()),
+                                    includes: vec!("*.txt".to_string(), "*.swp".to_string()),
+                                    ..ScanOptions::default() };
+        let filter = Filter::new("root", &options);
+        assert!(filter.is_excluded("root/a/file.swp", false));
+        assert!(!filter.is_excluded("root/a/keep.swp", false));
+        assert!(filter.is_excluded("root/.git", true));
+        assert!(!filter.is_excluded("root/a", true));
+        assert!(!filter.is_excluded("root/a/file.txt", false));
+        assert!(filter.is_excluded("root/a/file.jpg", false));
+        // Only configured ignore files are excluded by default
+        let filter = Filter::new("root", &ScanOptions::default());
+        assert!(filter.is_excluded("root/a/.compare_tree_ignore", false));
+        assert!(!filter.is_excluded("root/a/.gitignore", false));
+        assert!(!filter.is_excluded("root/.compare_tree_ignore", true));
+        let filter = Filter::new("root", &ScanOptions { gitignore: true, ..ScanOptions::default() });
+        assert!(filter.is_excluded("root/a/.gitignore", false));
+        let filter = Filter::new("root", &ScanOptions { gitignore: true, excludes: vec!("!.gitignore".to_string()), ..ScanOptions::default() });
+        assert!(!filter.is_excluded("root/a/.gitignore", false));
+    }
+}
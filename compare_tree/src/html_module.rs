@@ -0,0 +1,256 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::output_module::OutputModule;
+use crate::ct_utils::html_escape;
+use crate::dump_tree::DumpTree;
+use crate::filetree_info::FileTreeInfo;
+
+const STYLE: &str = r#"body { font-family: sans-serif; font-size: 14px; }
+.trees { display: flex; gap: 2em; }
+.tree { flex: 1; overflow-x: auto; }
+details > details, details > div { margin-left: 1.5em; }
+.dup { background: #fde2e2; }
+.internal { background: #fff3cd; }
+.hit > summary, div.hit { outline: 2px solid #1e66f5; }
+.size { color: #666; margin-left: 0.5em; }
+.bar { display: inline-block; width: 80px; height: 8px; margin-left: 0.5em; background: #ddd; }
+.bar > span { display: block; height: 100%; background: #d20f39; }
+a { margin-left: 0.5em; text-decoration: none; }
+"#;
+
+const SCRIPT: &str = r#"function reveal(e) {
+  for (var p = e.parentElement; p; p = p.parentElement) {
+    if (p.tagName === 'DETAILS') { p.open = true; }
+  }
+}
+function go() {
+  var e = document.getElementById(location.hash.substring(1));
+  if (e) { reveal(e); e.scrollIntoView(); }
+}
+window.addEventListener('hashchange', go);
+document.getElementById('search').addEventListener('input', function() {
+  var q = this.value.toLowerCase();
+  var n = 0;
+  document.querySelectorAll('.entry').forEach(function(e) {
+    var hit = q.length > 0 && e.dataset.name.toLowerCase().indexOf(q) >= 0;
+    e.classList.toggle('hit', hit);
+    if (hit) { n++; reveal(e); }
+  });
+  document.getElementById('count').textContent = q.length > 0 ? n + ' matching entries' : '';
+});
+"#;
+
+/// Entry reported by comparison, with the id of its matching entry
+struct Mark {
+      class: &'static str,
+      target: String
+}
+
+/// Tree displayed in report, with marks of its reported entries
+struct TreeView {
+      side: char,
+      tree: DumpTree,
+      marks: Vec<Option<Mark>>,
+      /// Size of duplicated entries found in each directory, only computed for other tree
+      reclaimable: Option<Vec<u64>>
+}
+
+/// Self-contained HTML page showing both trees with duplicated entries highlighted.
+/// Results are kept until the end as the page is generated from dumps once comparison is done
+pub struct HtmlModule {
+      filename: String,
+      /// Root and dump file of each tree
+      reference: (String, String),
+      other: (String, String),
+      duplicated: Vec<(String, String)>,
+      internal: Vec<(String, String)>
+}
+
+impl HtmlModule {
+      pub fn new(filename: &str, reference: (&str, String), other: (&str, String)) -> HtmlModule {
+            HtmlModule { filename: filename.to_string(),
+                         reference: (reference.0.to_string(), reference.1),
+                         other: (other.0.to_string(), other.1),
+                         duplicated: Vec::new(),
+                         internal: Vec::new() }
+      }
+
+      fn render(&self) -> String {
+            let mut page = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>compare_tree report</title>\n");
+            page.push_str(&format!("<style>\n{}</style>\n</head>\n<body>\n", STYLE));
+            page.push_str(&format!("<h1>{} &harr; {}</h1>\n", html_escape(&self.reference.0), html_escape(&self.other.0)));
+
+            let mut views = Vec::new();
+            for (side, (root, dump)) in [('r', &self.reference), ('o', &self.other)] {
+                  match DumpTree::load(dump, root) {
+                        Ok(tree) => views.push(Some(TreeView { side, marks: (0..tree.entries.len()).map(|_| None).collect(), tree, reclaimable: None })),
+                        Err(e) => {
+                              eprintln!("Tree {} not in HTML report : {}", root, e);
+                              views.push(None);
+                        }
+                  }
+            }
+            let indexes = views.iter().map(|view| view.as_ref().map(|v| v.tree.entries.iter().enumerate().map(|(i, e)| (e.name.as_str(), i)).collect::<HashMap<&str, usize>>())).collect::<Vec<_>>();
+            let find = |side: usize, name: &str| indexes[side].as_ref().and_then(|index| index.get(name).copied());
+            let id = |side: usize, name: &str| find(side, name).map(|i| format!("{}{}", ['r', 'o'][side], i)).unwrap_or_default();
+
+            let mut marks: Vec<(usize, usize, Mark)> = Vec::new();
+            let mut reclaimable_size = 0;
+            for (reference, other) in self.duplicated.iter() {
+                  if let Some(i) = find(0, reference) {
+                        marks.push((0, i, Mark { class: "dup", target: id(1, other) }));
+                  }
+                  if let Some(i) = find(1, other) {
+                        marks.push((1, i, Mark { class: "dup", target: id(0, reference) }));
+                  }
+            }
+            for (first, second) in self.internal.iter() {
+                  if let Some(i) = find(1, second) {
+                        marks.push((1, i, Mark { class: "internal", target: id(1, first) }));
+                  }
+            }
+            for (side, index, mark) in marks {
+                  if let Some(view) = views[side].as_mut() {
+                        // A reference entry may match several other entries, first one is linked
+                        if view.marks[index].is_none() {
+                              view.marks[index] = Some(mark);
+                        }
+                  }
+            }
+            if let Some(view) = views[1].as_mut() {
+                  let mut reclaimable = vec!(0; view.tree.entries.len());
+                  for index in 0..view.tree.entries.len() {
+                        if view.marks[index].as_ref().is_some_and(|m| m.class == "dup") {
+                              let size = view.tree.entries[index].size;
+                              reclaimable_size += size;
+                              view.tree.ancestors(index).iter().for_each(|a| reclaimable[*a] += size);
+                        }
+                  }
+                  view.reclaimable = Some(reclaimable);
+            }
+
+            page.push_str(&format!("<p>{} duplicated entries, {} bytes reclaimable, {} entries duplicated inside other tree</p>\n",
+                                   self.duplicated.len(), reclaimable_size, self.internal.len()));
+            page.push_str("<p><input id=\"search\" type=\"search\" placeholder=\"Search\"> <span id=\"count\"></span></p>\n<div class=\"trees\">\n");
+            for (view, root) in views.iter().zip([&self.reference.0, &self.other.0]) {
+                  page.push_str("<div class=\"tree\">\n");
+                  match view {
+                        Some(view) => render_entry(view, view.tree.root(), &mut page),
+                        None => page.push_str(&format!("<p>Dump of {} is not available</p>\n", html_escape(root)))
+                  }
+                  page.push_str("</div>\n");
+            }
+            page.push_str(&format!("</div>\n<script>\n{}</script>\n</body>\n</html>\n", SCRIPT));
+            page
+      }
+}
+
+fn render_entry(view: &TreeView, index: usize, page: &mut String) {
+      let entry = &view.tree.entries[index];
+      let label = if index == view.tree.root() {
+            entry.name.as_str()
+      } else {
+            Path::new(&entry.name).file_name().and_then(|n| n.to_str()).unwrap_or(&entry.name)
+      };
+      let mark = view.marks[index].as_ref();
+      let class = match mark {
+            Some(mark) => format!("entry {}", mark.class),
+            None => String::from("entry")
+      };
+      let mut line = format!("{}<span class=\"size\">{} bytes</span>", html_escape(label), entry.size);
+      if let Some(reclaimable) = view.reclaimable.as_ref().filter(|_| entry.height > 0 && entry.size > 0) {
+            let percent = reclaimable[index] * 100 / entry.size;
+            line.push_str(&format!("<span class=\"bar\" title=\"{} bytes reclaimable\"><span style=\"width: {}%\"></span></span>", reclaimable[index], percent.min(100)));
+      }
+      if let Some(mark) = mark.filter(|m| !m.target.is_empty()) {
+            line.push_str(&format!("<a href=\"#{}\" title=\"matching entry\">&#8644;</a>", mark.target));
+      }
+      let attributes = format!("id=\"{}{}\" class=\"{}\" data-name=\"{}\"", view.side, index, class, html_escape(&entry.name));
+      let mut children = view.tree.children(index).to_vec();
+      if children.is_empty() {
+            page.push_str(&format!("<div {}>{}</div>\n", attributes, line));
+            return;
+      }
+      children.sort_by(|a, b| view.tree.entries[*a].name.cmp(&view.tree.entries[*b].name));
+      page.push_str(&format!("<details {}><summary>{}</summary>\n", attributes, line));
+      children.iter().for_each(|child| render_entry(view, *child, page));
+      page.push_str("</details>\n");
+}
+
+impl OutputModule for HtmlModule {
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            self.internal.push((first.name.clone(), second.name.clone()));
+      }
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            self.duplicated.push((reference.name.clone(), other.name.clone()));
+            Ok(true)
+      }
+      fn treat_hardlinked(&mut self, _first: &FileTreeInfo, _second: &FileTreeInfo) {
+      }
+}
+
+impl Drop for HtmlModule {
+    fn drop(&mut self) {
+        let page = self.render();
+        match fs::write(&self.filename, page) {
+            Ok(()) => println!("==> HTML report written in {}", self.filename),
+            Err(e) => eprintln!("!!! Unable to write file {} : {}", self.filename, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+
+    fn write_dump(filename: &str, root: &str, entries: &[FileTreeInfo]) {
+        let mut content = format!("# header\n# root={}\n", root);
+        entries.iter().for_each(|e| content.push_str(&format!("{}\n", e)));
+        fs::write(filename, content).expect("Unable to write dump");
+    }
+
+    #[test]
+    fn check_html_module() {
+        let name = "report_html_test.html";
+        write_dump("html_ref_dump.txt", "ref", &[FileTreeInfo::for_test("a", "a", 0, 0, 10), FileTreeInfo::for_test("<b>", "b", 0, 0, 30), FileTreeInfo::for_test(".", "ref", 1, 0, 40)]);
+        write_dump("html_oth_dump.txt", "oth", &[FileTreeInfo::for_test("sub/a", "a", 0, 0, 10), FileTreeInfo::for_test("sub/c", "c", 0, 0, 30), FileTreeInfo::for_test("sub", "sub", 1, 0, 40), FileTreeInfo::for_test("a2", "a", 0, 0, 10), FileTreeInfo::for_test(".", "oth", 2, 0, 50)]);
+        {
+            let mut module = HtmlModule::new(name, ("ref", "html_ref_dump.txt".to_string()), ("oth", "html_oth_dump.txt".to_string()));
+            module.treat_internal_doublon(&FileTreeInfo::for_test("oth/sub/a", "a", 0, 0, 10), &FileTreeInfo::for_test("oth/a2", "a", 0, 0, 10));
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("ref/a", "a", 0, 0, 10), &FileTreeInfo::for_test("oth/sub/a", "a", 0, 0, 10)).unwrap());
+        }
+        let page = fs::read_to_string(name).expect("Unable to read report");
+        assert!(page.contains("<div id=\"r0\" class=\"entry dup\" data-name=\"ref/a\">a<span class=\"size\">10 bytes</span><a href=\"#o0\""));
+        assert!(page.contains("<div id=\"o0\" class=\"entry dup\" data-name=\"oth/sub/a\">a<span class=\"size\">10 bytes</span><a href=\"#r0\""));
+        assert!(page.contains("<div id=\"o3\" class=\"entry internal\" data-name=\"oth/a2\">a2<span class=\"size\">10 bytes</span><a href=\"#o0\""));
+        assert!(page.contains("data-name=\"ref/&lt;b&gt;\">&lt;b&gt;"));
+        assert!(page.contains("title=\"10 bytes reclaimable\"><span style=\"width: 25%\">"));
+        assert!(page.contains("title=\"10 bytes reclaimable\"><span style=\"width: 20%\">"));
+        assert!(page.contains("1 duplicated entries, 10 bytes reclaimable, 1 entries duplicated inside other tree"));
+        // Self-contained page
+        assert!(!page.contains("http") && !page.contains(" src="));
+        assert!(fs::remove_file(name).is_ok());
+        assert!(fs::remove_file("html_ref_dump.txt").is_ok());
+        assert!(fs::remove_file("html_oth_dump.txt").is_ok());
+    }
+}
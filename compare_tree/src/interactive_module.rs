@@ -19,13 +19,57 @@ use crate::output_module::OutputModule;
 use std::fs;
 use std::io;
 use crate::ct_utils::despecialise;
+use crate::filetree_info::FileTreeInfo;
+use crate::journal::{self, Journal};
+use crate::sha1::Sha1Key;
 
 pub struct InteractiveModule {
+      /// Reference tree is not available on this machine so its files can not be checked
+      pub offline_reference: bool,
+      /// Removals are recorded so that they can be undone
+      pub journal: Journal
+}
+
+fn is_directory(name: &str) -> bool {
+      fs::symlink_metadata(name).is_ok_and(|m| m.is_dir())
+}
+
+/// Command submitted to user, with number of files and size of a directory
+fn removal(name: &str) -> Result<String, String> {
+      if !is_directory(name) {
+            return Ok(format!("rm {}", despecialise(name)));
+      }
+      let (nb_files, size) = journal::directory_size(name)?;
+      Ok(format!("rm -r {} ({} files, {} bytes)", despecialise(name), nb_files, size))
+}
+
+impl InteractiveModule {
+      /// Directory content is checked again against reference directory before being removed
+      fn remove(&mut self, name: &str, reference: &str, scanned: &Sha1Key) {
+            let result = if is_directory(name) {
+                  self.journal.remove_directory(name, reference)
+            } else {
+                  self.journal.remove_file(name, reference, scanned)
+            };
+            if let Err(e) = result {
+                  eprintln!("!!! {} not removed : {}", despecialise(name), e);
+            }
+      }
 }
 
 impl OutputModule for InteractiveModule {
-      fn treat_internal_doublon(&mut self, first: &str, second: &str) {
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            let scanned = &first.sha1;
+            let first = first.name.as_str();
+            let second = second.name.as_str();
             eprintln!("!!! Doublon {} <-> {}", first, second);
+            match (removal(first), removal(second)) {
+                  (Ok(remove_first), Ok(remove_second)) => println!("rf : {}\nrs : {}", remove_first, remove_second),
+                  (Err(e), _) | (_, Err(e)) => {
+                        eprintln!("!!! {}", e);
+                        return;
+                  }
+            }
             println!("What to do ? (rf/rs/s)");
             let mut answer = String::new();
             while answer == "" {
@@ -36,11 +80,11 @@ impl OutputModule for InteractiveModule {
                   }
                   println!("Your answer is '{}'", answer);
                   if answer == "rf".to_string() {
-                        fs::remove_file(&first).expect("Unable to remove file");
+                        self.remove(first, second, scanned);
                         break;
                   }
                   else if answer == "rs".to_string() {
-                        fs::remove_file(&second).expect("Unable to remove file");
+                        self.remove(second, first, scanned);
                         break;
                   }
                   else if answer == "s".to_string() {
@@ -49,9 +93,12 @@ impl OutputModule for InteractiveModule {
             }
       }
 
-      fn treat_duplicated(&mut self, reference: &str, other: &str) -> Result<bool, String> {
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            let scanned = &other.sha1;
+            let reference = reference.name.as_str();
+            let other = other.name.as_str();
             eprintln!("{} TO REMOVE {}", reference, other);
-            let exist_ref_result = fs::exists(&reference);
+            let exist_ref_result = if self.offline_reference { Ok(true) } else { fs::exists(&reference) };
             let exist_ref = match exist_ref_result {
                 Ok(r) => r,
                 Err(e) => return Err(format!("Error when trying to check if {} exists : {}", reference, e).into())
@@ -64,7 +111,18 @@ impl OutputModule for InteractiveModule {
 
             if exist_ref && exist_oth {
                   eprintln!("{} TO REMOVE {}", despecialise(&reference), despecialise(&other));
-                  println!("rm {} ? (y/n/q)", despecialise(&other));
+                  if self.offline_reference && is_directory(other) {
+                        eprintln!("!!! {} not removed : content of offline reference directory can not be checked", despecialise(other));
+                        return Ok(true);
+                  }
+                  let command = match removal(other) {
+                        Ok(command) => command,
+                        Err(e) => {
+                              eprintln!("!!! {} not removed : {}", despecialise(other), e);
+                              return Ok(true);
+                        }
+                  };
+                  println!("{} ? (y/n/q)", command);
                   let mut answer = String::new();
                   while answer == "" {
                         io::stdin().read_line(&mut answer).expect("Failed to read line");
@@ -74,7 +132,7 @@ impl OutputModule for InteractiveModule {
                         }
                         println!("Your answer is '{}'", answer);
                         if answer == "y".to_string() {
-                              fs::remove_file(&other).expect("Unable to remove file");
+                              self.remove(other, reference, scanned);
                               break;
                         }
                         else if answer == "n".to_string() {
@@ -87,4 +145,8 @@ impl OutputModule for InteractiveModule {
             }
             Ok(true)
       }
+
+      fn treat_hardlinked(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            eprintln!("=== Hardlinked {} <-> {}", first.name, second.name);
+      }
 }
@@ -0,0 +1,478 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::ct_utils::{despecialise, escape_field, unescape_field};
+use crate::filetree_info::EntryKind;
+use crate::sha1::Sha1Key;
+
+/// Journal when none is given
+pub const DEFAULT_JOURNAL: &str = "compare_tree_journal.tsv";
+const JOURNAL_HEADER: &str = "date\tkind\toriginal\tsha1\tsize\tmode\tmtime\ttarget\treference";
+
+/// Removed entry. Undo rebuilds a file from its reference file and a link from its target
+#[derive(Debug, PartialEq)]
+pub struct JournalRecord {
+      /// Seconds since epoch when entry was removed
+      pub date: u64,
+      pub kind: EntryKind,
+      pub original: String,
+      pub sha1: String,
+      pub size: u64,
+      pub mode: u32,
+      pub mtime: i64,
+      /// Target of a link, empty otherwise
+      pub target: String,
+      pub reference: String
+}
+
+impl JournalRecord {
+      fn line(&self) -> String {
+            format!("{}\t{}\t{}\t{}\t{}\t{:o}\t{}\t{}\t{}\n", self.date, self.kind.name(), escape_field(&self.original), self.sha1, self.size, self.mode, self.mtime,
+                    escape_field(&self.target), escape_field(&self.reference))
+      }
+
+      fn parse(line: &str) -> Option<JournalRecord> {
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            if fields.len() != 9 {
+                  return None;
+            }
+            Some(JournalRecord { date: fields[0].parse().ok()?,
+                                 kind: [EntryKind::File, EntryKind::Link, EntryKind::Directory].into_iter().find(|k| k.name() == fields[1])?,
+                                 original: unescape_field(fields[2]),
+                                 sha1: fields[3].to_string(),
+                                 size: fields[4].parse().ok()?,
+                                 mode: u32::from_str_radix(fields[5], 8).ok()?,
+                                 mtime: fields[6].parse().ok()?,
+                                 target: unescape_field(fields[7]),
+                                 reference: unescape_field(fields[8]) })
+      }
+}
+
+/// Append-only journal of removed files, each removal is recorded before being done
+pub struct Journal {
+      filename: String,
+      file: File,
+      /// Reference files are not available on this machine so they can not be checked
+      offline_reference: bool
+}
+
+/// Entry of a directory to remove with an entry of reference directory having the same content
+pub struct DuplicatedEntry {
+      pub name: PathBuf,
+      pub reference: PathBuf,
+      pub sha1: Sha1Key,
+      pub metadata: fs::Metadata
+}
+
+/// Entries of a directory except directories, recursively without following links
+fn directory_entries(name: &Path, entries: &mut Vec<(PathBuf, fs::Metadata)>) -> Result<(), String> {
+      let iter = fs::read_dir(name).map_err(|e| format!("Unable to read directory {} : {}", name.display(), e))?;
+      for entry in iter {
+            let path = entry.map_err(|e| format!("Unable to read directory {} : {}", name.display(), e))?.path();
+            let metadata = fs::symlink_metadata(&path).map_err(|e| format!("Unable to collect metadata from {} : {}", path.display(), e))?;
+            if metadata.is_dir() {
+                  directory_entries(&path, entries)?;
+            } else {
+                  entries.push((path, metadata));
+            }
+      }
+      Ok(())
+}
+
+/// Directories of a directory, including it, children before their parent
+fn directories(name: &Path, result: &mut Vec<(PathBuf, fs::Metadata)>) -> Result<(), String> {
+      let iter = fs::read_dir(name).map_err(|e| format!("Unable to read directory {} : {}", name.display(), e))?;
+      for entry in iter {
+            let entry = entry.map_err(|e| format!("Unable to read directory {} : {}", name.display(), e))?;
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                  directories(&entry.path(), result)?;
+            }
+      }
+      let metadata = fs::symlink_metadata(name).map_err(|e| format!("Unable to collect metadata from {} : {}", name.display(), e))?;
+      result.push((name.to_path_buf(), metadata));
+      Ok(())
+}
+
+fn entry_sha1(path: &Path, metadata: &fs::Metadata) -> Result<Sha1Key, String> {
+      let name = path.to_str().ok_or(format!("to_str() issue with {}", path.display()))?;
+      if metadata.is_file() {
+            crate::compute_file_sha1(name)
+      } else if metadata.is_symlink() {
+            crate::compute_link_sha1(name)
+      } else {
+            Err(format!("{} is a special file", name))
+      }
+}
+
+/// Number of entries which are not directories and total size of files of a directory
+pub fn directory_size(name: &str) -> Result<(usize, u64), String> {
+      let mut entries = Vec::new();
+      directory_entries(Path::new(name), &mut entries)?;
+      Ok((entries.len(), entries.iter().filter(|(_, m)| m.is_file()).map(|(_, m)| m.len()).sum()))
+}
+
+/// Check again just before removal that each entry of a directory has a copy in reference directory,
+/// whatever its path since duplicated directories may have different names when names are not hashed
+pub fn directory_copies(reference: &str, name: &str) -> Result<Vec<DuplicatedEntry>, String> {
+      let mut reference_entries = Vec::new();
+      directory_entries(Path::new(reference), &mut reference_entries)?;
+      let mut references = HashMap::new();
+      for (path, metadata) in reference_entries {
+            references.insert(entry_sha1(&path, &metadata)?, path);
+      }
+      let mut entries = Vec::new();
+      directory_entries(Path::new(name), &mut entries)?;
+      let mut copies = Vec::new();
+      for (path, metadata) in entries {
+            let sha1 = entry_sha1(&path, &metadata)?;
+            match references.get(&sha1) {
+                  Some(reference) => copies.push(DuplicatedEntry { name: path, reference: reference.clone(), sha1, metadata }),
+                  None => return Err(format!("{} has no copy in {}", path.display(), reference))
+            }
+      }
+      Ok(copies)
+}
+
+/// Absolute path of an entry whose parent directory exists, given path otherwise.
+/// Entry itself is not resolved since it may be a link
+fn absolute_name(name: &str) -> String {
+      let path = Path::new(name);
+      let parent = match path.parent() {
+            Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+            Some(parent) => parent,
+            None => return name.to_string()
+      };
+      match (fs::canonicalize(parent), path.file_name()) {
+            (Ok(parent), Some(file_name)) => parent.join(file_name).to_str().map(String::from).unwrap_or(name.to_string()),
+            _ => name.to_string()
+      }
+}
+
+impl Journal {
+      pub fn open(filename: &str, offline_reference: bool) -> Result<Journal, String> {
+            let new_journal = !Path::new(filename).exists();
+            let mut file = match OpenOptions::new().create(true).append(true).open(filename) {
+                  Ok(file) => file,
+                  Err(e) => return Err(format!("Unable to open file {} : {}", filename, e))
+            };
+            if new_journal && let Err(e) = file.write_all(format!("{}\n", JOURNAL_HEADER).as_bytes()) {
+                  return Err(format!("Unable to write in file {} : {}", filename, e));
+            }
+            Ok(Journal { filename: filename.to_string(), file, offline_reference })
+      }
+
+      /// Directories have no SHA1 nor reference
+      fn write_record(&mut self, name: &str, sha1: &str, metadata: &fs::Metadata, reference: &str) -> Result<(), String> {
+            let date = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let (kind, target) = if metadata.is_dir() {
+                  (EntryKind::Directory, String::new())
+            } else if metadata.is_symlink() {
+                  match fs::read_link(name).map(|t| t.to_str().map(String::from)) {
+                        Ok(Some(target)) => (EntryKind::Link, target),
+                        _ => return Err(format!("Fail to read path of link {}", name))
+                  }
+            } else {
+                  (EntryKind::File, String::new())
+            };
+            let reference = if reference.is_empty() { String::new() } else { absolute_name(reference) };
+            let record = JournalRecord { date, kind, original: absolute_name(name), sha1: sha1.to_string(), size: metadata.len(),
+                                         mode: metadata.mode() & 0o7777, mtime: metadata.mtime(), target, reference };
+            self.file.write_all(record.line().as_bytes()).map_err(|e| format!("Unable to write in file {} : {}", self.filename, e))
+      }
+
+      /// Records are on disk before removal so that a crash during it loses nothing
+      fn sync(&mut self) -> Result<(), String> {
+            self.file.sync_data().map_err(|e| format!("Unable to write in file {} : {}", self.filename, e))
+      }
+
+      /// Record removal of a file or a link duplicating reference entry then remove it. It must still
+      /// have the SHA1 of the scan and reference must still have the same content, otherwise removed
+      /// file could never be rebuilt. An offline reference can only be checked through the scan
+      pub fn remove_file(&mut self, name: &str, reference: &str, scanned: &Sha1Key) -> Result<(), String> {
+            let metadata = |name: &str| fs::symlink_metadata(name).map_err(|e| format!("Unable to collect metadata from {} : {}", name, e));
+            let name_metadata = metadata(name)?;
+            let sha1 = entry_sha1(Path::new(name), &name_metadata)?;
+            if &sha1 != scanned {
+                  return Err(format!("content of {} changed since scan", name));
+            }
+            if self.offline_reference {
+                  eprintln!("!!! Removal of {} can not be undone on this machine : reference {} is offline", despecialise(name), despecialise(reference));
+            } else if entry_sha1(Path::new(reference), &metadata(reference)?)? != sha1 {
+                  return Err(format!("content of reference {} differs", reference));
+            }
+            let metadata = name_metadata;
+            self.write_record(name, &sha1.to_string(), &metadata, reference)?;
+            self.sync()?;
+            fs::remove_file(name).map_err(|e| format!("Unable to remove {} : {}", name, e))
+      }
+
+      /// Check that each entry of a directory has a copy in reference directory then record
+      /// removal of its files, links and directories and remove it. Directories are recorded
+      /// after their children so that undo creates them before their content
+      pub fn remove_directory(&mut self, name: &str, reference: &str) -> Result<(), String> {
+            let copies = directory_copies(reference, name)?;
+            for copy in copies.iter() {
+                  let (name, reference) = (copy.name.to_string_lossy(), copy.reference.to_string_lossy());
+                  self.write_record(&name, &copy.sha1.to_string(), &copy.metadata, &reference)?;
+            }
+            let mut removed = Vec::new();
+            directories(Path::new(name), &mut removed)?;
+            for (path, metadata) in removed.iter() {
+                  self.write_record(&path.to_string_lossy(), "", metadata, "")?;
+            }
+            self.sync()?;
+            fs::remove_dir_all(name).map_err(|e| format!("Unable to remove {} : {}", name, e))
+      }
+}
+
+pub fn read_journal(filename: &str) -> Result<Vec<JournalRecord>, String> {
+      let content = match fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(e) => return Err(format!("Unable to read file {} : {}", filename, e))
+      };
+      let mut lines = content.lines();
+      if lines.next() != Some(JOURNAL_HEADER) {
+            return Err(format!("{} is not a journal", filename));
+      }
+      lines.enumerate().map(|(number, line)| JournalRecord::parse(line).ok_or(format!("Invalid line {} in {}", number + 2, filename))).collect()
+}
+
+/// Create parent directories of a removed entry and return the path it is rebuilt at
+fn prepare(original: &Path) -> Result<PathBuf, String> {
+      if let Some(parent) = original.parent() && let Err(e) = fs::create_dir_all(parent) {
+            return Err(format!("unable to create directory {} : {}", parent.display(), e));
+      }
+      let name = original.file_name().and_then(|n| n.to_str()).ok_or(format!("No file name in {}", original.display()))?;
+      Ok(original.with_file_name(format!(".{}.compare_tree.{}", name, std::process::id())))
+}
+
+fn record_mtime(record: &JournalRecord) -> SystemTime {
+      if record.mtime >= 0 { UNIX_EPOCH + Duration::from_secs(record.mtime as u64) } else { UNIX_EPOCH - Duration::from_secs(record.mtime.unsigned_abs()) }
+}
+
+/// Copy reference file in place of removed one with its permissions and modification time,
+/// or create again a removed link or directory. Entry is created next to original path then
+/// renamed so that a partial copy is never visible. Directories get their permissions and
+/// modification time once their content is rebuilt
+fn rebuild(record: &JournalRecord) -> Result<(), String> {
+      let original = Path::new(&record.original);
+      if fs::symlink_metadata(original).is_ok() {
+            return Err(String::from("original path exists"));
+      }
+      match record.kind {
+            EntryKind::Link => return rebuild_link(record),
+            EntryKind::Directory => return fs::create_dir_all(original).map_err(|e| format!("unable to create directory : {}", e)),
+            _ => {}
+      }
+      match crate::compute_file_sha1(&record.reference) {
+            Ok(sha1) if sha1.to_string() == record.sha1 => {},
+            Ok(_) => return Err(format!("content of reference {} has changed", record.reference)),
+            Err(e) => return Err(e)
+      }
+      let temporary = prepare(original)?;
+      let mtime = record_mtime(record);
+      let result = File::open(&record.reference).and_then(|mut source| {
+            let mut file = OpenOptions::new().write(true).create_new(true).open(&temporary)?;
+            io::copy(&mut source, &mut file)?;
+            file.set_modified(mtime)?;
+            // Permissions are set last through the same handle since a read-only copy can not be opened for writing
+            file.set_permissions(fs::Permissions::from_mode(record.mode))
+      }).and_then(|_| fs::rename(&temporary, original));
+      if let Err(e) = result {
+            let _ = fs::remove_file(&temporary);
+            return Err(format!("unable to copy {} : {}", record.reference, e));
+      }
+      Ok(())
+}
+
+/// Permissions are set last since a read-only directory can not be modified anymore
+fn restore_directory(record: &JournalRecord) -> Result<(), String> {
+      File::open(&record.original).and_then(|directory| directory.set_modified(record_mtime(record)))
+            .and_then(|_| fs::set_permissions(&record.original, fs::Permissions::from_mode(record.mode)))
+            .map_err(|e| format!("unable to set permissions and modification time : {}", e))
+}
+
+fn rebuild_link(record: &JournalRecord) -> Result<(), String> {
+      let original = Path::new(&record.original);
+      let temporary = prepare(original)?;
+      if let Err(e) = std::os::unix::fs::symlink(&record.target, &temporary).and_then(|_| fs::rename(&temporary, original)) {
+            let _ = fs::remove_file(&temporary);
+            return Err(format!("unable to create link to {} : {}", record.target, e));
+      }
+      Ok(())
+}
+
+/// Rebuild entries removed in journal, latest first. Entries whose original path exists again are skipped.
+/// Rebuilt directories get their attributes back at the end, children before their parent
+pub fn undo(filename: &str) -> Result<(), String> {
+      let (mut nb_rebuilt, mut nb_present, mut nb_failed) = (0, 0, 0);
+      let records = read_journal(filename)?;
+      let mut rebuilt_directories = Vec::new();
+      for record in records.iter().rev() {
+            if fs::symlink_metadata(&record.original).is_ok() {
+                  nb_present += 1;
+                  continue;
+            }
+            match rebuild(record) {
+                  Ok(()) => {
+                        match record.kind {
+                              EntryKind::Directory => {
+                                    eprintln!("{} REBUILT", despecialise(&record.original));
+                                    rebuilt_directories.push(record);
+                              },
+                              EntryKind::Link => eprintln!("{} REBUILT FROM {}", despecialise(&record.original), despecialise(&record.target)),
+                              _ => eprintln!("{} REBUILT FROM {}", despecialise(&record.original), despecialise(&record.reference))
+                        }
+                        nb_rebuilt += 1;
+                  },
+                  Err(reason) => {
+                        eprintln!("!!! {} not rebuilt : {}", despecialise(&record.original), reason);
+                        nb_failed += 1;
+                  }
+            }
+      }
+      for record in rebuilt_directories.iter().rev() {
+            if let Err(reason) = restore_directory(record) {
+                  eprintln!("!!! {} not rebuilt : {}", despecialise(&record.original), reason);
+                  nb_rebuilt -= 1;
+                  nb_failed += 1;
+            }
+      }
+      println!("==> {} files rebuilt, {} already present, {} failed", nb_rebuilt, nb_present, nb_failed);
+      if nb_failed > 0 {
+            return Err(format!("{} files could not be rebuilt", nb_failed));
+      }
+      Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_journal() {
+        let root = Path::new("journal25");
+        fs::create_dir_all(root.join("ref")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth/sub")).expect("Unable to create directory");
+        for name in ["ref/a", "oth/sub/a", "ref/b", "oth/b", "ref/r", "oth/r"] {
+            fs::write(root.join(name), "Same").expect("Unable to write file");
+        }
+        fs::write(root.join("ref/c"), "Diff").expect("Unable to write file");
+        std::os::unix::fs::symlink("a", root.join("ref/l")).expect("Unable to create link");
+        std::os::unix::fs::symlink("a", root.join("oth/l")).expect("Unable to create link");
+        fs::set_permissions(root.join("oth/sub/a"), fs::Permissions::from_mode(0o640)).expect("Unable to set permissions");
+        fs::set_permissions(root.join("oth/r"), fs::Permissions::from_mode(0o444)).expect("Unable to set permissions");
+        File::options().write(true).open(root.join("oth/sub/a")).expect("Unable to open file").set_modified(UNIX_EPOCH + Duration::from_secs(1000)).expect("Unable to set time");
+        let same = crate::compute_file_sha1("journal25/ref/a").expect("Unable to hash file");
+        let link = crate::compute_link_sha1("journal25/ref/l").expect("Unable to hash link");
+        let name = "journal25/journal.tsv";
+        {
+            let mut journal = Journal::open(name, false).expect("Unable to open journal");
+            assert!(journal.remove_file("journal25/oth/sub", "journal25/ref", &same).is_err());
+            // Reference differs so removed file could not be rebuilt
+            assert!(journal.remove_file("journal25/oth/sub/a", "journal25/ref/c", &same).is_err());
+            assert!(root.join("oth/sub/a").exists());
+            // Content changed since scan
+            assert!(journal.remove_file("journal25/oth/sub/a", "journal25/ref/a", &link).is_err());
+            assert_eq!(Ok(()), journal.remove_file("journal25/oth/sub/a", "journal25/ref/a", &same));
+        }
+        fs::remove_dir(root.join("oth/sub")).expect("Unable to remove directory");
+        // Journal is appended
+        let mut journal = Journal::open(name, false).expect("Unable to open journal");
+        assert_eq!(Ok(()), journal.remove_file("journal25/oth/b", "journal25/ref/b", &same));
+        assert!(journal.remove_file("journal25/oth/l", "journal25/ref/a", &link).is_err());
+        assert_eq!(Ok(()), journal.remove_file("journal25/oth/l", "journal25/ref/l", &link));
+        assert_eq!(Ok(()), journal.remove_file("journal25/oth/r", "journal25/ref/r", &same));
+        let records = read_journal(name).expect("Unable to read journal");
+        assert_eq!(4, records.len());
+        assert_eq!((EntryKind::Link, "a"), (records[2].kind, records[2].target.as_str()));
+        let absolute = fs::canonicalize(root).expect("No test directory");
+        assert_eq!((absolute.join("oth/sub/a").to_str().unwrap(), absolute.join("ref/a").to_str().unwrap(), 4, 0o640, 1000),
+                   (records[0].original.as_str(), records[0].reference.as_str(), records[0].size, records[0].mode, records[0].mtime));
+        assert_eq!(crate::compute_file_sha1("journal25/ref/a").unwrap().to_string(), records[0].sha1);
+
+        // Reference b changed so its duplicate can not be rebuilt
+        fs::write(root.join("ref/b"), "Diff").expect("Unable to write file");
+        assert!(undo(name).is_err());
+        assert!(!root.join("oth/b").exists());
+        let metadata = fs::metadata(root.join("oth/sub/a")).expect("File not rebuilt");
+        assert_eq!((0o640, 1000), (metadata.mode() & 0o7777, metadata.mtime()));
+        assert_eq!("Same", fs::read_to_string(root.join("oth/sub/a")).expect("Unable to read file"));
+        fs::write(root.join("ref/b"), "Same").expect("Unable to write file");
+        assert_eq!(Ok(()), undo(name));
+        assert_eq!("Same", fs::read_to_string(root.join("oth/b")).expect("Unable to read file"));
+        assert_eq!(Path::new("a"), fs::read_link(root.join("oth/l")).expect("Link not rebuilt"));
+        // Read-only file gets its permissions and modification time back
+        let metadata = fs::metadata(root.join("oth/r")).expect("File not rebuilt");
+        assert_eq!((0o444, records[3].mtime), (metadata.mode() & 0o7777, metadata.mtime()));
+        // With reference offline, content is only checked against the scan
+        fs::write(root.join("oth/o"), "Same").expect("Unable to write file");
+        let mut journal = Journal::open("journal25/offline.tsv", true).expect("Unable to open journal");
+        assert!(journal.remove_file("journal25/oth/o", "/archive/o", &link).is_err());
+        assert!(root.join("oth/o").exists());
+        assert_eq!(Ok(()), journal.remove_file("journal25/oth/o", "/archive/o", &same));
+        assert!(!root.join("oth/o").exists());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+
+    #[test]
+    fn check_remove_directory() {
+        let root = Path::new("directory26");
+        fs::create_dir_all(root.join("ref/sub")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth/copy/renamed")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth/copy/empty")).expect("Unable to create directory");
+        for (name, content) in [("ref/a", "A"), ("ref/sub/b", "Bb"), ("oth/copy/a", "A"), ("oth/copy/renamed/b", "Bb")] {
+            fs::write(root.join(name), content).expect("Unable to write file");
+        }
+        std::os::unix::fs::symlink("a", root.join("ref/link")).expect("Unable to create link");
+        std::os::unix::fs::symlink("a", root.join("oth/copy/link")).expect("Unable to create link");
+        // Private and read-only directories get their permissions back
+        fs::set_permissions(root.join("oth/copy/empty"), fs::Permissions::from_mode(0o555)).expect("Unable to set permissions");
+        fs::set_permissions(root.join("oth/copy"), fs::Permissions::from_mode(0o700)).expect("Unable to set permissions");
+        assert_eq!(Ok((3, 3)), directory_size("directory26/oth/copy"));
+        let name = "directory26/journal.tsv";
+        let mut journal = Journal::open(name, false).expect("Unable to open journal");
+        // Content changed since scan
+        fs::write(root.join("oth/copy/c"), "C").expect("Unable to write file");
+        assert!(journal.remove_directory("directory26/oth/copy", "directory26/ref").is_err());
+        assert!(root.join("oth/copy/c").exists());
+        fs::remove_file(root.join("oth/copy/c")).expect("Unable to remove file");
+        File::open(root.join("oth/copy")).expect("Unable to open directory").set_modified(UNIX_EPOCH + Duration::from_secs(1000)).expect("Unable to set time");
+        assert_eq!(Ok(()), journal.remove_directory("directory26/oth/copy", "directory26/ref"));
+        assert!(!root.join("oth/copy").exists());
+        assert_eq!(6, read_journal(name).expect("Unable to read journal").len());
+        assert_eq!(Ok(()), undo(name));
+        let metadata = fs::metadata(root.join("oth/copy")).expect("Directory not rebuilt");
+        assert_eq!((0o700, 1000), (metadata.mode() & 0o7777, metadata.mtime()));
+        assert_eq!(0o555, fs::metadata(root.join("oth/copy/empty")).expect("Directory not rebuilt").mode() & 0o7777);
+        assert_eq!("Bb", fs::read_to_string(root.join("oth/copy/renamed/b")).expect("Unable to read file"));
+        assert_eq!(Path::new("a"), fs::read_link(root.join("oth/copy/link")).expect("Link not rebuilt"));
+        assert!(root.join("oth/copy/empty").is_dir());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+}
@@ -21,7 +21,10 @@ use std::io::Read;
 use std::io::BufWriter;
 use std::io::BufReader;
 use std::io;
+use std::path::Path;
 use std::path::PathBuf;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::MetadataExt;
 use std::io::prelude::*;
 
 
@@ -32,26 +35,114 @@ mod output_module;
 mod display_module;
 mod interactive_module;
 mod batch_module;
+mod report_module;
+mod csv_module;
+mod html_module;
+mod replace_module;
+mod quarantine_module;
+mod dump_tree;
+mod diff_module;
+mod scan_options;
+mod near_module;
+mod filter;
+mod scan_summary;
+mod journal;
 
 use crate::output_module::OutputModule;
+use crate::diff_module::CommonEntries;
+use crate::scan_options::ScanOptions;
+use crate::filetree_info::EntryKind;
+use crate::filetree_info::Inode;
+use crate::near_module::NearOptions;
+use crate::near_module::SimilarityMetric;
+use crate::filter::SizeFilter;
+use crate::filter::EmptyFilePolicy;
+use crate::scan_summary::ScanSummary;
+use crate::csv_module::CsvColumn;
+use crate::replace_module::ReplaceMethod;
+use crate::ct_utils::{full_name, relative_name};
+use std::str::FromStr;
+use std::collections::HashMap;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Default)]
 enum UseMode {
+    #[default]
     Print,
     Interactive,
-    Batch
+    Batch,
+    Diff,
+    Snapshot,
+    Near,
+    Export,
+    Report,
+    Csv,
+    Html,
+    Link,
+    Quarantine,
+    /// Move back quarantined entries located in given paths, all entries if none
+    Restore(Vec<String>),
+    /// Remove entries quarantined for more than given number of days
+    Purge(u64),
+    /// Rebuild files removed in journal from their reference
+    Undo
+}
+
+/// State of a scan shared by all directories
+struct ScanContext {
+    /// Scanned root, paths are written in dump relatively to it
+    root: String,
+    /// Device of scanned root
+    device: u64,
+    /// (device, inode) of directories being scanned, from root to current directory
+    ancestors: Vec<(u64, u64)>,
+    /// SHA1 of already hashed files having several hardlinks
+    inode_hashes: HashMap<(u64, u64), sha1::Sha1Key>,
+    summary: ScanSummary
 }
 
-fn analyse_filetree(path: PathBuf, output: &mut impl Write) -> Result<filetree_info::FileTreeInfo, String> {
+impl ScanContext {
+    fn new(root: &str) -> Result<ScanContext, String> {
+        match fs::metadata(root) {
+            Ok(metadata) => Ok(ScanContext { root: root.to_string(), device: metadata.dev(), ancestors: Vec::new(), inode_hashes: HashMap::new(), summary: ScanSummary::default() }),
+            Err(e) => Err(format!("Unable to collect metadata from {} : {}", root, e))
+        }
+    }
+
+    fn write_entry(&self, entry: &filetree_info::FileTreeInfo, output: &mut impl Write) -> Result<(), String> {
+        let relative = filetree_info::FileTreeInfo { name: relative_name(&self.root, &entry.name), ..entry.clone() };
+        match output.write_all(format!("{}\n", relative).as_bytes()) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Unable to write result of {} : {}", entry.name, e))
+        }
+    }
+}
+
+fn analyse_filetree(path: PathBuf, options: &ScanOptions, filter: &mut filter::Filter, context: &mut ScanContext, output: &mut impl Write) -> Result<filetree_info::FileTreeInfo, String> {
     let string_path = path.to_str().ok_or(format!("to_str() issue with {}", path.display()))?;
+    // An unreadable sub-directory is recorded as an error entry, scanned root must be readable
+    let tolerated = options.keep_going && !context.ancestors.is_empty();
 
     // Get iterator to list directory content
     let dir_iter_result = fs::read_dir(string_path);
     let dir_iter = match dir_iter_result {
         Ok(dir_iter) => dir_iter,
+        Err(e) if tolerated => return record_error(string_path, &e.to_string(), context, output),
         Err(_e) => return Err(format!("problem with dir_iter on {}", string_path).into())
     };
+    let key = match fs::metadata(string_path) {
+        Ok(metadata) => (metadata.dev(), metadata.ino()),
+        Err(e) if tolerated => return record_error(string_path, &e.to_string(), context, output),
+        Err(e) => return Err(format!("Unable to collect metadata from {} : {}", string_path, e))
+    };
+    match filter.enter(string_path) {
+        Ok(()) => {},
+        Err(e) if tolerated => return record_error(string_path, &e, context, output),
+        Err(e) => return Err(e)
+    }
+    context.ancestors.push(key);
+    let nb_errors = context.summary.errors.len() + context.summary.unstable.len();
     let mut nb_item: u32 = 0;
+    let mut size: u64 = 0;
     let mut height: u32 = 1;
     let mut keys = Vec::new();
 
@@ -60,76 +151,279 @@ fn analyse_filetree(path: PathBuf, output: &mut impl Write) -> Result<filetree_i
 
         let item = match item_result {
             Ok(item) => item,
+            Err(e) if options.keep_going => {
+                // Entry has no name so only the directory is known as incomplete
+                context.summary.errors.push((string_path.to_string(), e.to_string()));
+                continue;
+            },
             Err(_e) => return Err(format!("Issue with item").into())
         };
 
         let item_path = item.path();
         if cfg!(test) { println!("Analyse => {}", item_path.display()); }
         let item_path_str = item_path.to_str().ok_or(format!("to_str() issue with {}", item.path().display()))?;
+        let item_name = item_path.file_name().and_then(|n| n.to_str()).ok_or(format!("file_name() issue with {}", item_path.display()))?;
 
         // Get item metadata
-        let metadata_result = item.metadata();
-        if metadata_result.is_err() {
-            return Err(format!("Unable to collect metadata from file {}", item_path_str).into());
+        let mut metadata = match item.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if options.keep_going => {
+                let error = record_error(item_path_str, &e.to_string(), context, output)?;
+                keys.push((error.sha1, item_name.to_string(), EntryKind::Error));
+                nb_item += 1;
+                continue;
+            },
+            Err(_e) => return Err(format!("Unable to collect metadata from file {}", item_path_str).into())
+        };
+
+        if metadata.is_symlink() {
+            match fs::metadata(item_path_str) {
+                Err(_) => {
+                    if cfg!(test) { println!("{} is a dangling link", item_path_str); }
+                    context.summary.dangling_links.push(item_path_str.to_string());
+                },
+                // Link to an ancestor directory is kept as a link to avoid endless recursion
+                Ok(target) if options.follow_links && target.is_dir() && context.ancestors.contains(&(target.dev(), target.ino())) => {
+                    if cfg!(test) { println!("{} is a link loop", item_path_str); }
+                    context.summary.link_loops.push(item_path_str.to_string());
+                },
+                Ok(target) if options.follow_links => metadata = target,
+                Ok(_) => {}
+            }
+        }
+
+        // Filtered items are not part of the hash and do not count as items
+        if filter.is_excluded(item_path_str, metadata.is_dir()) {
+            if cfg!(test) { println!("{} is excluded", item_path_str); }
+            continue;
         }
-        let metadata = metadata_result.unwrap();
 
         // Treat items depending on its type
         if metadata.is_dir() {
+            if options.one_filesystem && metadata.dev() != context.device {
+                if cfg!(test) { println!("{} is on another filesystem", item_path_str); }
+                context.summary.skipped_mount_points.push(item_path_str.to_string());
+                continue;
+            }
             if cfg!(test) { println!("{} is a directory", item_path_str); }
-            let filetree_info = analyse_filetree(item.path(), output)?;
+            let filetree_info = analyse_filetree(item.path(), options, filter, context, output)?;
+            if filetree_info.kind == EntryKind::Error {
+                keys.push((filetree_info.sha1, item_name.to_string(), EntryKind::Error));
+                nb_item += 1;
+                continue;
+            }
             nb_item += filetree_info.nb_item;
-            // Ignore empty directories
-            if filetree_info.nb_item != 0 {
-                keys.push(filetree_info.sha1);
+            size += filetree_info.size;
+            // Ignore empty directories unless layout is part of the hash
+            if filetree_info.nb_item != 0 || options.hash_names {
+                keys.push((filetree_info.sha1, item_name.to_string(), filetree_info.kind));
                 if height < filetree_info.height + 1 {
                     height = filetree_info.height + 1;
                 }
             }
         }
         if metadata.is_file() || metadata.is_symlink() {
-            let sha1 = if metadata.is_file() {
-                if cfg!(test) { println!("{} is a file", item_path_str); }
-                compute_file_sha1(item_path_str)?
-            } else {
-                if cfg!(test) { println!("{} is a link", item_path_str); }
-                compute_link_sha1(item_path_str)?
-
+            let result = match hash_item(item_path_str, &metadata, options, context) {
+                Ok(result) => result,
+                Err(e) if options.keep_going => {
+                    let error = record_error(item_path_str, &e, context, output)?;
+                    keys.push((error.sha1, item_name.to_string(), EntryKind::Error));
+                    nb_item += 1;
+                    continue;
+                },
+                Err(e) => return Err(e)
             };
+            context.write_entry(&result, output)?;
+            keys.push((result.sha1, item_name.to_string(), result.kind));
+            nb_item += 1;
+            size += result.size;
+        }
+        if let Some(special_type) = special_type(&metadata.file_type()) {
+            if cfg!(test) { println!("{} is a {}", item_path_str, special_type); }
+            context.summary.nb_special += 1;
+            if !options.hash_special {
+                continue;
+            }
+            let sha1 = compute_special_sha1(special_type, metadata.rdev());
             let result = filetree_info::FileTreeInfo{name: String::from(item_path_str),
+                                                     kind: EntryKind::Special,
                                                      height: 0,
                                                      sha1: sha1.clone(),
-                                                     nb_item: 0};
-            let write_result = output.write(format!("{}\n", result).as_bytes());
-            if write_result.is_err() {
-                return Err(format!("Unable to write result of {}", item_path_str).into());
-            }
-            keys.push(sha1);
+                                                     nb_item: 0,
+                                                     size: 0,
+                                                     inode: None};
+            context.write_entry(&result, output)?;
+            keys.push((sha1, item_name.to_string(), EntryKind::Special));
             nb_item += 1;
         }
     }
     if cfg!(test) { println!("Analyse => {} items at this level", nb_item); }
-    // Sort SHA1 keys to be independant of directory listing order
-    keys.sort();
-    //keys.iter().for_each(|x| println!("{x:?}"));
+    // Directory with unreadable or unstable content at any depth can never be equivalent to another one
+    let kind = if context.summary.errors.len() + context.summary.unstable.len() > nb_errors { EntryKind::Incomplete } else { EntryKind::Directory };
+    let mut data = Vec::from(kind.domain());
+    if options.hash_names {
+        // Names are unique in a directory so sorting by name is independant of directory listing order
+        keys.sort_by(|a, b| a.1.cmp(&b.1));
+        // Converts all names + types + sha1 to bytes, a null byte ends each name
+        for (sha1, name, kind) in keys.iter() {
+            data.extend(name.as_bytes());
+            data.push(0);
+            if options.hash_types {
+                data.push(kind.tag() as u8);
+            }
+            data.extend(sha1.to_bytes());
+        }
+    }
+    else {
+        // Sort SHA1 keys to be independant of directory listing order
+        keys.sort();
+        //keys.iter().for_each(|x| println!("{x:?}"));
 
-    // Converts all sha1 + number of items to byte in order to compute SHA1 of this directory
-    let mut data = Vec::<u8>::new();
-    keys.iter().for_each(|k|data.extend(k.to_bytes()));
+        // Converts all sha1 to bytes
+        keys.iter().for_each(|(k, _, _)|data.extend(k.to_bytes()));
+    }
+    // Add number of items in order to compute SHA1 of this directory
     data.extend(nb_item.to_le_bytes());
 
     let result = filetree_info::FileTreeInfo{name: string_path.into(),
+                                             kind,
                                              height: height,
                                              sha1: sha1::compute_sha1(data),
-                                             nb_item: nb_item};
-    let write_result = output.write(format!("{}\n", result).as_bytes());
-    if write_result.is_err() {
-        return Err(format!("Unable to write result of {}", string_path).into());
+                                             nb_item: nb_item,
+                                             size,
+                                             inode: None};
+    context.write_entry(&result, output)?;
+    filter.leave();
+    context.ancestors.pop();
+
+    Ok(result)
+}
+
+/// File was not modified between both metadata collections
+fn unchanged(before: &fs::Metadata, after: &fs::Metadata) -> bool {
+    before.len() == after.len() &&
+    (before.mtime(), before.mtime_nsec()) == (after.mtime(), after.mtime_nsec()) &&
+    (before.ctime(), before.ctime_nsec()) == (after.ctime(), after.ctime_nsec())
+}
+
+/// Hash a file until it is not modified while being read, retrying the configured
+/// number of times. Content of a hardlinked file is read only once
+fn hash_file(path: &str, metadata: &fs::Metadata, options: &ScanOptions, context: &mut ScanContext) -> Result<filetree_info::FileTreeInfo, String> {
+    let inode = Inode { dev: metadata.dev(), ino: metadata.ino(), nlink: metadata.nlink() };
+    let mut result = filetree_info::FileTreeInfo{name: String::from(path),
+                                                 kind: EntryKind::File,
+                                                 height: 0,
+                                                 sha1: sha1::compute_sha1(vec!()),
+                                                 nb_item: 0,
+                                                 size: metadata.len(),
+                                                 inode: Some(inode)};
+    if let Some(sha1) = context.inode_hashes.get(&(inode.dev, inode.ino)) {
+        result.sha1 = sha1.clone();
+        return Ok(result);
     }
+    let mut before = metadata.clone();
+    for attempt in 0..options.retries + 1 {
+        result.sha1 = compute_file_sha1(path)?;
+        let after = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(e) => return Err(format!("Unable to collect metadata from file {} : {}", path, e))
+        };
+        result.size = after.len();
+        if unchanged(&before, &after) {
+            if inode.nlink > 1 {
+                context.inode_hashes.insert((inode.dev, inode.ino), result.sha1.clone());
+            }
+            return Ok(result);
+        }
+        if cfg!(test) { println!("{} modified during attempt {}", path, attempt); }
+        before = after;
+    }
+    context.summary.unstable.push(path.to_string());
+    result.kind = EntryKind::Unstable;
+    Ok(result)
+}
+
+/// Hash a file or a link
+fn hash_item(path: &str, metadata: &fs::Metadata, options: &ScanOptions, context: &mut ScanContext) -> Result<filetree_info::FileTreeInfo, String> {
+    if metadata.is_file() {
+        if cfg!(test) { println!("{} is a file", path); }
+        hash_file(path, metadata, options, context)
+    } else {
+        if cfg!(test) { println!("{} is a link", path); }
+        Ok(filetree_info::FileTreeInfo{name: String::from(path),
+                                       kind: EntryKind::Link,
+                                       height: 0,
+                                       sha1: compute_link_sha1(path)?,
+                                       nb_item: 0,
+                                       size: metadata.len(),
+                                       inode: None})
+    }
+}
 
+/// Hash an entry of root again whatever its dump says, to check it just before acting on it.
+/// Directories from root to the entry are entered first, as during the scan of root
+fn rehash_entry(root: &str, name: &str, options: &ScanOptions) -> Result<filetree_info::FileTreeInfo, String> {
+    let relative = match Path::new(name).strip_prefix(root) {
+        Ok(relative) => relative,
+        Err(_) => return Err(format!("{} is not in {}", name, root))
+    };
+    let mut metadata = match fs::symlink_metadata(name) {
+        Ok(metadata) => metadata,
+        Err(e) => return Err(format!("Unable to collect metadata from {} : {}", name, e))
+    };
+    if metadata.is_symlink() && options.follow_links && let Ok(target) = fs::metadata(name) {
+        metadata = target;
+    }
+    let mut context = ScanContext::new(root)?;
+    if metadata.is_dir() {
+        let mut filter = filter::Filter::new(root, options);
+        let mut dirs = Vec::new();
+        if let Some(parent) = relative.parent() {
+            dirs.push(PathBuf::from(root));
+            for component in parent.components() {
+                dirs.push(dirs[dirs.len() - 1].join(component));
+            }
+        }
+        for dir in dirs {
+            let dir_str = dir.to_str().ok_or(format!("to_str() issue with {}", dir.display()))?;
+            match fs::metadata(dir_str) {
+                Ok(dir_metadata) => context.ancestors.push((dir_metadata.dev(), dir_metadata.ino())),
+                Err(e) => return Err(format!("Unable to collect metadata from {} : {}", dir_str, e))
+            }
+            filter.enter(dir_str)?;
+        }
+        analyse_filetree(PathBuf::from(name), options, &mut filter, &mut context, &mut io::sink())
+    } else if metadata.is_file() || metadata.is_symlink() {
+        hash_item(name, &metadata, options, &mut context)
+    } else {
+        Err(format!("{} is a special file", name))
+    }
+}
+
+/// Unreadable entry is kept in the dump, preceded by a comment giving the error.
+/// Its SHA1 depends on its path so that it never matches another entry
+fn record_error(path: &str, error: &str, context: &mut ScanContext, output: &mut impl Write) -> Result<filetree_info::FileTreeInfo, String> {
+    if cfg!(test) { println!("{} is unreadable : {}", path, error); }
+    context.summary.errors.push((path.to_string(), error.to_string()));
+    let mut data = Vec::from(EntryKind::Error.domain());
+    data.extend(path.as_bytes());
+    let result = filetree_info::FileTreeInfo{name: String::from(path),
+                                             kind: EntryKind::Error,
+                                             height: 0,
+                                             sha1: sha1::compute_sha1(data),
+                                             nb_item: 0,
+                                             size: 0,
+                                             inode: None};
+    if output.write_all(format!("# error {} : {}\n", relative_name(&context.root, path), error).as_bytes()).is_err() {
+        return Err(format!("Unable to write result of {}", path));
+    }
+    context.write_entry(&result, output)?;
     Ok(result)
 }
 
+/// Second line of a dump, giving the root the paths of the dump are relative to
+const ROOT_PREFIX: &str = "# root=";
+
 fn dump_name(name: & str) -> String {
     let mut filename = String::from(name);
     filename = filename.replace("/", "_");
@@ -152,19 +446,46 @@ fn split_name(name: &str, height: u32) -> String {
     return filename;
 }
 
-fn analyse(name: &str) -> Result<filetree_info::FileTreeInfo, String> {
+fn analyse(name: &str, options: &ScanOptions) -> Result<filetree_info::FileTreeInfo, String> {
+    let options = &options.for_root(name);
     let filename = dump_name(name);
     let file = File::create(&filename).expect(format!("Unable to create file {}", filename).as_str());
     let mut buf = BufWriter::new(file);
+    if let Err(e) = buf.write_all(format!("{}\n{}{}\n", options.header(), ROOT_PREFIX, name).as_bytes()) {
+        return Err(format!("Unable to write header of {} : {}", filename, e));
+    }
     let mut path = PathBuf::new();
     path.push(name);
-    analyse_filetree(path, &mut buf)
+    let mut filter = filter::Filter::new(name, options);
+    let mut context = ScanContext::new(name)?;
+    let result = analyse_filetree(path, options, &mut filter, &mut context, &mut buf)?;
+    context.summary.display(name, options);
+    Ok(result)
 }
 
-fn generate_dump(name: &str) -> Result<u32, String> {
+fn read_dump_header(name: &str) -> Result<String, String> {
+    let file = match File::open(dump_name(name)) {
+        Ok(f) => f,
+        Err(e) => return Err(format!("Unable to open file {} {}", dump_name(name), e))
+    };
+    let mut reader = BufReader::new(file);
+    let mut line = String::new();
+    if let Err(e) = reader.read_line(&mut line) {
+        return Err(format!("Unable to read from {} : {}", dump_name(name), e));
+    }
+    Ok(line.trim_end_matches('\n').to_string())
+}
+
+fn generate_dump(name: &str, options: &ScanOptions) -> Result<u32, String> {
+    let options = &options.for_root(name);
     let result: u32;
     let check = fs::exists(dump_name(name));
-    if check.is_ok() && check.unwrap() {
+    let mut reuse = check.is_ok() && check.unwrap();
+    if reuse && read_dump_header(name)? != options.header() {
+        println!("==> Existing dump for {} was generated with other options", name);
+        reuse = false;
+    }
+    if reuse {
         println!("==> Parse existing dump for {}", name);
         let file_result = File::open(dump_name(name));
         let file = match file_result {
@@ -186,7 +507,7 @@ fn generate_dump(name: &str) -> Result<u32, String> {
     }
     else {
         println!("==> Generate dump for {}", name);
-        let analyse = analyse(name)?;
+        let analyse = analyse(name, options)?;
         result = analyse.height;
         let check = fs::exists(dump_dir(name));
         if check.is_err() {
@@ -202,6 +523,85 @@ fn generate_dump(name: &str) -> Result<u32, String> {
     Ok(result)
 }
 
+fn load_dump(name: &str) -> Result<dump_tree::DumpTree, String> {
+    dump_tree::DumpTree::load(&dump_name(name), name)
+}
+
+/// Read a dump, returning its options header, the root it was generated from and its entries
+fn read_dump(filename: &str) -> Result<(String, String, Vec<String>), String> {
+    let content = match fs::read_to_string(filename) {
+        Ok(content) => content,
+        Err(e) => return Err(format!("Unable to read file {} : {}", filename, e))
+    };
+    let mut lines = content.lines();
+    let header = lines.next().unwrap_or_default().to_string();
+    let root = match lines.next().and_then(|line| line.strip_prefix(ROOT_PREFIX)) {
+        Some(root) => root.to_string(),
+        None => return Err(format!("{} is not a dump recording its root", filename))
+    };
+    Ok((header, root, lines.map(String::from).collect()))
+}
+
+/// Write a dump whose paths are relative to the given root
+fn write_dump(filename: &str, header: &str, root: &str, entries: &[String]) -> Result<(), String> {
+    let mut content = format!("{}\n{}{}\n", header, ROOT_PREFIX, root);
+    entries.iter().for_each(|line| { content.push_str(line); content.push('\n'); });
+    match fs::write(filename, content) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Unable to write file {} : {}", filename, e))
+    }
+}
+
+fn remove_splits(name: &str) -> Result<(), String> {
+    if fs::exists(dump_dir(name)).unwrap_or(false) && fs::remove_dir_all(dump_dir(name)).is_err() {
+        return Err(format!("Unable to clean directory {}", dump_dir(name)));
+    }
+    Ok(())
+}
+
+/// Write a copy of the dump of a directory recording its absolute location,
+/// so that it can be used as reference on another machine
+fn export_dump(name: &str, filename: &str, options: &ScanOptions) -> Result<(), String> {
+    generate_dump(name, options)?;
+    let root = match fs::canonicalize(name) {
+        Ok(path) => path.to_str().ok_or(format!("to_str() issue with {}", path.display()))?.to_string(),
+        Err(e) => return Err(format!("Unable to canonicalise {} : {}", name, e))
+    };
+    let (header, _, entries) = read_dump(&dump_name(name))?;
+    write_dump(filename, &header, &root, &entries)?;
+    println!("==> Dump of {} exported in {}", name, filename);
+    Ok(())
+}
+
+/// Install an exported dump as the dump of the root recorded in it.
+/// Return this root which is used as reference name
+fn import_dump(filename: &str, options: &ScanOptions) -> Result<String, String> {
+    let (header, root, entries) = read_dump(filename)?;
+    if header != options.header() {
+        return Err(format!("Reference dump {} was generated with options '{}' instead of '{}'", filename, header, options.header()));
+    }
+    write_dump(&dump_name(&root), &header, &root, &entries)?;
+    // Splits of a previous import are obsolete
+    remove_splits(&root)?;
+    println!("==> Reference dump {} imported for {}", filename, root);
+    Ok(root)
+}
+
+/// Move the dump of a tree which has been moved or mounted elsewhere, so that it is
+/// reused instead of scanning the tree again
+fn rebase_dump(old: &str, new: &str) -> Result<(), String> {
+    let (header, _, entries) = read_dump(&dump_name(old))?;
+    write_dump(&dump_name(new), &header, new, &entries)?;
+    if dump_name(old) != dump_name(new) && fs::remove_file(dump_name(old)).is_err() {
+        return Err(format!("Unable to remove file {}", dump_name(old)));
+    }
+    // Splits contain full paths so they are regenerated
+    remove_splits(old)?;
+    remove_splits(new)?;
+    println!("==> Dump of {} rebased on {}", old, new);
+    Ok(())
+}
+
 fn generate_split(name: &str, height: u32) -> Result<(), String> {
         println!("==> Prepare split for '{}'", name);
         let check = fs::exists(dump_dir(name));
@@ -238,7 +638,13 @@ fn generate_split(name: &str, height: u32) -> Result<(), String> {
                         Ok(l) => l,
                         Err(e) => return Err(format!("Unable to read from {} : {}", dump_name(name), e))
                     };
-                    let filetree_info = filetree_info::FileTreeInfo::from(&line)?;
+                    // Skip dump header
+                    if line.starts_with('#') {
+                        continue;
+                    }
+                    let entry = filetree_info::FileTreeInfo::from(&line)?;
+                    // Splits are read while comparing so they use the current location of the tree
+                    let filetree_info = filetree_info::FileTreeInfo { name: full_name(name, &entry.name), ..entry };
                     assert!((filetree_info.height as usize) < files.len());
                     let write_result = files[filetree_info.height as usize].write(format!("{}\n", filetree_info).as_bytes());
                     if write_result.is_err() {
@@ -304,6 +710,70 @@ fn check_directory(name: &str ) -> Result<bool, String> {
     Ok(true)
 }
 
+/// Relation between compared directories
+#[derive(Debug, PartialEq)]
+enum Overlap {
+    Disjoint,
+    /// Path of reference relative to other
+    ReferenceInOther(PathBuf),
+    /// Path of other relative to reference
+    OtherInReference(PathBuf)
+}
+
+/// Compare canonical paths of both directories. Identical directories are refused
+fn check_overlap(reference: &str, other: &str) -> Result<Overlap, String> {
+    let canonical = |name: &str| match fs::canonicalize(name) {
+        Ok(path) => Ok(path),
+        Err(e) => Err(format!("Unable to canonicalise {} : {}", name, e))
+    };
+    let ref_path = canonical(reference)?;
+    let oth_path = canonical(other)?;
+    if ref_path == oth_path {
+        return Err(format!("{} and {} are the same directory", reference, other));
+    }
+    if let Ok(relative) = ref_path.strip_prefix(&oth_path) {
+        return Ok(Overlap::ReferenceInOther(relative.to_path_buf()));
+    }
+    if let Ok(relative) = oth_path.strip_prefix(&ref_path) {
+        return Ok(Overlap::OtherInReference(relative.to_path_buf()));
+    }
+    Ok(Overlap::Disjoint)
+}
+
+/// Scan options excluding the nested directory from the scan of the outer one.
+/// Nested directories are refused unless explicitly asked. When links are followed, a link of one tree
+/// may lead into the other one, so both roots are excluded from the scans
+fn nested_scan_options(configuration: &Config) -> Result<ScanOptions, String> {
+    let mut scan_options = configuration.scan_options.clone();
+    let canonical = |name: &str| match fs::canonicalize(name) {
+        Ok(path) => path.to_str().map(|p| p.to_string()).ok_or(format!("to_str() issue with {}", path.display())),
+        Err(e) => Err(format!("Unable to canonicalise {} : {}", name, e))
+    };
+    let nested = match check_overlap(&configuration.reference_path, &configuration.other_path)? {
+        Overlap::Disjoint => None,
+        Overlap::ReferenceInOther(relative) => Some((&configuration.other_path, &configuration.reference_path, relative)),
+        Overlap::OtherInReference(relative) => Some((&configuration.reference_path, &configuration.other_path, relative))
+    };
+    if let Some((outer, inner, relative)) = nested {
+        let nested = PathBuf::from(outer).join(relative);
+        let nested_str = nested.to_str().ok_or(format!("to_str() issue with {}", nested.display()))?;
+        if !configuration.exclude_nested {
+            return Err(format!("{} is nested in {}, use --exclude-nested to exclude it from the scan of {}", nested_str, outer, outer));
+        }
+        println!("==> {} is excluded from the scan of {}", nested_str, outer);
+        scan_options.excluded_paths.push(canonical(inner)?);
+    }
+    if scan_options.follow_links {
+        for root in [&configuration.reference_path, &configuration.other_path] {
+            let root = canonical(root)?;
+            if !scan_options.excluded_paths.contains(&root) {
+                scan_options.excluded_paths.push(root);
+            }
+        }
+    }
+    Ok(scan_options)
+}
+
 fn consume(io_iter: &mut io::Lines<io::BufReader<File>>) ->Result<String, String> {
     let result_iter = io_iter.next();
     if result_iter.is_none() {
@@ -316,9 +786,20 @@ fn consume(io_iter: &mut io::Lines<io::BufReader<File>>) ->Result<String, String
     return Ok(result);
 }
 
+/// When links are followed, an entry reached through a link is the same file or directory
+/// as its target, so they must not be reported as duplicated
+fn aliases(first: &str, second: &str) -> bool {
+    match (fs::canonicalize(first), fs::canonicalize(second)) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false
+    }
+}
+
 fn compare_iter(mut reference: io::Lines<io::BufReader<File>> ,
                 mut other: io::Lines<io::BufReader<File>>,
-                to_remove: &mut Vec<(String, String)>) -> Result<(), String> {
+                size_filter: &SizeFilter,
+                follow_links: bool,
+                to_remove: &mut Vec<(filetree_info::FileTreeInfo, filetree_info::FileTreeInfo)>) -> Result<(), String> {
     // File can never be empty as splitted files are created when encoutering a FileTreeInfo
     let mut ref_item = filetree_info::FileTreeInfo::from(&consume(&mut reference)?)?;
     let mut other_line = consume(&mut other)?;
@@ -329,12 +810,15 @@ fn compare_iter(mut reference: io::Lines<io::BufReader<File>> ,
         }
         let other_item = filetree_info::FileTreeInfo::from(&other_line)?;
         let other_len = other_item.name.chars().count();
-        if to_remove.iter().any(|(_, x)| { let removed = String::from(x) + "/"; removed.chars().count () <= other_len && &removed == &(other_item.name.chars().take(removed.chars().count()).collect::<String>())}) {
+        if to_remove.iter().any(|(_, x)| { let removed = String::from(&x.name) + "/"; removed.chars().count () <= other_len && &removed == &(other_item.name.chars().take(removed.chars().count()).collect::<String>())}) {
             other_line = consume(&mut other)?;
             continue;
         }
         if ref_item.equivalent(&other_item) {
-            to_remove.push((ref_item.name.clone(), other_item.name));
+            // Equivalent entries have the same size so checking one of them is enough
+            if size_filter.accept(&other_item) && !(follow_links && aliases(&ref_item.name, &other_item.name)) {
+                to_remove.push((ref_item.clone(), other_item));
+            }
             other_line = consume(&mut other)?;
         }
         else if ref_item.sha1 < other_item.sha1 {
@@ -350,7 +834,7 @@ fn compare_iter(mut reference: io::Lines<io::BufReader<File>> ,
     }
 }
 
-fn check_duplicated(filename: &str, output_mod: &mut Box<dyn OutputModule>) -> Result<(), String> {
+fn check_duplicated(filename: &str, size_filter: &SizeFilter, follow_links: bool, output_mod: &mut Box<dyn OutputModule>) -> Result<(), String> {
         let file_result = File::open(&filename);
         let file = match file_result {
                 Ok(f) => f,
@@ -358,8 +842,11 @@ fn check_duplicated(filename: &str, output_mod: &mut Box<dyn OutputModule>) -> R
         };
         let reader = BufReader::new(file);
         let mut previous_filetree = filetree_info::FileTreeInfo { name: String::from("")
+                                             , kind: EntryKind::File
                                              , height: 0
                                              , nb_item: 0
+                                             , size: 0
+                                             , inode: None
                                              , sha1: sha1::compute_sha1(vec!())
                                              };
         for line_result in reader.lines() {
@@ -368,19 +855,12 @@ fn check_duplicated(filename: &str, output_mod: &mut Box<dyn OutputModule>) -> R
                 Err(e) => return Err(format!("Unable to read line from file {} {}", filename, e))
             };
             let filetree_info = filetree_info::FileTreeInfo::from(&line)?;
-            if filetree_info.sha1 == previous_filetree.sha1 {
-                let exist_prev_result = fs::exists(&previous_filetree.name);
-                let exist_prev = match exist_prev_result {
-                    Ok(r) => r,
-                    Err(e) => return Err(format!("Error when trying to check if {} exists : {}", previous_filetree.name, e))
-                };
-                let exist_cur_result = fs::exists(&filetree_info.name);
-                let exist_cur = match exist_cur_result {
-                    Ok(r) => r,
-                    Err(e) => return Err(format!("Error when trying to check if {} exists : {}", previous_filetree.name, e))
-                };
-                if exist_prev && exist_cur {
-                    output_mod.treat_internal_doublon(&previous_filetree.name, &filetree_info.name)
+            if filetree_info.sha1 == previous_filetree.sha1 && size_filter.accept(&filetree_info) && !(follow_links && aliases(&previous_filetree.name, &filetree_info.name)) {
+                if filetree_info.same_inode(&previous_filetree) {
+                    output_mod.treat_hardlinked(&previous_filetree, &filetree_info);
+                }
+                else {
+                    report_internal_doublon(&previous_filetree, &filetree_info, output_mod)?;
                 }
             }
             previous_filetree = filetree_info;
@@ -388,7 +868,24 @@ fn check_duplicated(filename: &str, output_mod: &mut Box<dyn OutputModule>) -> R
         Ok(())
 }
 
-fn compare(reference: &str, other: &str, height: u32, output_mod: &mut Box<dyn OutputModule>) -> Result<Vec<(String, String)>, String> {
+fn report_internal_doublon(first: &filetree_info::FileTreeInfo, second: &filetree_info::FileTreeInfo, output_mod: &mut Box<dyn OutputModule>) -> Result<(), String> {
+        let exist_prev_result = fs::exists(&first.name);
+        let exist_prev = match exist_prev_result {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Error when trying to check if {} exists : {}", first.name, e))
+        };
+        let exist_cur_result = fs::exists(&second.name);
+        let exist_cur = match exist_cur_result {
+            Ok(r) => r,
+            Err(e) => return Err(format!("Error when trying to check if {} exists : {}", second.name, e))
+        };
+        if exist_prev && exist_cur {
+            output_mod.treat_internal_doublon(first, second)
+        }
+        Ok(())
+}
+
+fn compare(reference: &str, other: &str, height: u32, size_filter: &SizeFilter, follow_links: bool, output_mod: &mut Box<dyn OutputModule>) -> Result<Vec<(filetree_info::FileTreeInfo, filetree_info::FileTreeInfo)>, String> {
     println!("==> Analyse");
     let mut to_remove = Vec::new();
     for i in (0..height + 1).rev() {
@@ -401,21 +898,21 @@ fn compare(reference: &str, other: &str, height: u32, output_mod: &mut Box<dyn O
         };
         let reader_ref = BufReader::new(file);
         let filename = split_name(other, i);
-        check_duplicated(&filename, output_mod)?;
+        check_duplicated(&filename, size_filter, follow_links, output_mod)?;
         let file_result = File::open(&filename);
         let file = match file_result {
                 Ok(f) => f,
                 Err(e) => return Err(format!("Unable to open file {} {}", filename, e))
         };
         let reader_other = BufReader::new(file);
-        compare_iter(reader_ref.lines(), reader_other.lines(), & mut to_remove)?;
+        compare_iter(reader_ref.lines(), reader_other.lines(), size_filter, follow_links, & mut to_remove)?;
     }
     Ok(to_remove)
 }
 
-fn compare_trees(reference: &str, other: &str, output_mod: &mut Box<dyn OutputModule>) -> Result<Vec<(String, String)>, String> {
-    let height_ref = generate_dump(reference)?;
-    let height_other = generate_dump(other)?;
+fn prepare_trees(reference: &str, other: &str, options: &ScanOptions) -> Result<u32, String> {
+    let height_ref = generate_dump(reference, options)?;
+    let height_other = generate_dump(other, options)?;
     println!("==> Dump result {} vs {}", height_ref, height_other);
 
     let common_height = if height_ref > height_other {height_other} else {height_ref};
@@ -423,43 +920,263 @@ fn compare_trees(reference: &str, other: &str, output_mod: &mut Box<dyn OutputMo
 
     generate_split(reference, height_ref)?;
     generate_split(other, height_other)?;
+    Ok(common_height)
+}
 
-    compare(reference, other, common_height, output_mod)
+fn compare_trees(reference: &str, other: &str, options: &ScanOptions, size_filter: &SizeFilter, output_mod: &mut Box<dyn OutputModule>) -> Result<Vec<(filetree_info::FileTreeInfo, filetree_info::FileTreeInfo)>, String> {
+    let common_height = prepare_trees(reference, other, options)?;
+    compare(reference, other, common_height, size_filter, options.follow_links, output_mod)
+}
+
+/// Index all reference entries whatever their height. When several entries have
+/// the same SHA1 the one with the smallest name is kept like in sorted split files
+fn reference_index(reference: &str) -> Result<HashMap<sha1::Sha1Key, filetree_info::FileTreeInfo>, String> {
+    let tree = load_dump(reference)?;
+    let mut index: HashMap<sha1::Sha1Key, filetree_info::FileTreeInfo> = HashMap::new();
+    for entry in tree.entries.into_iter() {
+        match index.get(&entry.sha1) {
+            Some(previous) if previous.name <= entry.name => {},
+            _ => { index.insert(entry.sha1.clone(), entry); }
+        }
+    }
+    Ok(index)
+}
+
+/// Look up every entry of other tree in reference index, starting from other root.
+/// Sub-entries of a matched entry are not looked up so whole subtrees are matched at once
+fn index_compare_trees(reference: &str, other: &str, options: &ScanOptions, size_filter: &SizeFilter, output_mod: &mut Box<dyn OutputModule>) -> Result<Vec<(filetree_info::FileTreeInfo, filetree_info::FileTreeInfo)>, String> {
+    generate_dump(reference, options)?;
+    generate_dump(other, options)?;
+    println!("==> Index reference");
+    let index = reference_index(reference)?;
+    println!("==> Analyse");
+    let other_tree = load_dump(other)?;
+
+    let mut first_seen: HashMap<&sha1::Sha1Key, &filetree_info::FileTreeInfo> = HashMap::new();
+    let mut doublons = Vec::new();
+    for entry in other_tree.entries.iter().filter(|e| size_filter.accept(e)) {
+        match first_seen.get(&entry.sha1) {
+            Some(first) if options.follow_links && aliases(&first.name, &entry.name) => {},
+            Some(first) => doublons.push((*first, entry)),
+            None => { first_seen.insert(&entry.sha1, entry); }
+        }
+    }
+    // Report entries sharing the same SHA1 one after the other like when comparing sorted dumps
+    doublons.sort_by(|a, b| a.0.sha1.cmp(&b.0.sha1));
+    for (first, entry) in doublons {
+        if first.same_inode(entry) {
+            output_mod.treat_hardlinked(first, entry);
+        } else {
+            report_internal_doublon(first, entry, output_mod)?;
+        }
+    }
+
+    let mut to_remove = Vec::new();
+    let mut stack = vec!(other_tree.root());
+    while let Some(current) = stack.pop() {
+        let entry = &other_tree.entries[current];
+        match index.get(&entry.sha1).filter(|r| r.equivalent(entry) && !(options.follow_links && aliases(&r.name, &entry.name))) {
+            Some(ref_item) => if size_filter.accept(entry) {
+                to_remove.push((ref_item.clone(), entry.clone()))
+            },
+            None => stack.extend(other_tree.children(current).iter().rev())
+        }
+    }
+    Ok(to_remove)
+}
+
+fn read_item(io_iter: &mut io::Lines<io::BufReader<File>>) -> Result<Option<filetree_info::FileTreeInfo>, String> {
+    let line = consume(io_iter)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(filetree_info::FileTreeInfo::from(&line)?))
+}
+
+fn common_iter(mut reference: io::Lines<io::BufReader<File>>,
+               mut other: io::Lines<io::BufReader<File>>,
+               common: &mut CommonEntries) -> Result<(), String> {
+    let mut ref_item = read_item(&mut reference)?;
+    let mut other_item = read_item(&mut other)?;
+    while let (Some(ref_info), Some(other_info)) = (&ref_item, &other_item) {
+        if ref_info.equivalent(other_info) {
+            if !common.contains_key(&ref_info.sha1) {
+                common.insert(ref_info.sha1.clone(), (ref_info.clone(), other_info.name.clone()));
+            }
+            other_item = read_item(&mut other)?;
+        }
+        else if (&ref_info.sha1, ref_info.nb_item) < (&other_info.sha1, other_info.nb_item) {
+            ref_item = read_item(&mut reference)?;
+        }
+        else {
+            other_item = read_item(&mut other)?;
+        }
+    }
+    Ok(())
+}
+
+fn common_entries(reference: &str, other: &str, options: &ScanOptions) -> Result<CommonEntries, String> {
+    let common_height = prepare_trees(reference, other, options)?;
+    println!("==> Diff");
+    let mut common = CommonEntries::new();
+    for i in 0..common_height + 1 {
+        let filename = split_name(reference, i);
+        let reader_ref = match File::open(&filename) {
+            Ok(f) => BufReader::new(f),
+            Err(e) => return Err(format!("Unable to open file {} {}", filename, e))
+        };
+        let filename = split_name(other, i);
+        let reader_other = match File::open(&filename) {
+            Ok(f) => BufReader::new(f),
+            Err(e) => return Err(format!("Unable to open file {} {}", filename, e))
+        };
+        common_iter(reader_ref.lines(), reader_other.lines(), &mut common)?;
+    }
+    Ok(common)
+}
+
+fn diff_trees(reference: &str, other: &str, options: &ScanOptions) -> Result<Vec<diff_module::DiffEntry>, String> {
+    let common = common_entries(reference, other, options)?;
+    let mut result = diff_module::classify(&load_dump(reference)?, &common, true);
+    result.extend(diff_module::classify(&load_dump(other)?, &common, false));
+    Ok(result)
+}
+
+fn snapshot_trees(old: &str, new: &str, options: &ScanOptions) -> Result<Vec<diff_module::DiffEntry>, String> {
+    let common = common_entries(old, new, options)?;
+    Ok(diff_module::classify_snapshot(&load_dump(old)?,
+                                      &load_dump(new)?,
+                                      &common))
+}
+
+fn run_diff(configuration: &Config, reference: &str, scan_options: &ScanOptions) -> Result<(), Box<dyn Error>> {
+    let (filename, result) = if configuration.mode == UseMode::Snapshot {
+        ("snapshot_report.tsv", snapshot_trees(reference, &configuration.other_path, scan_options)?)
+    } else {
+        ("diff_report.tsv", diff_trees(reference, &configuration.other_path, scan_options)?)
+    };
+    let mut diff_mod = diff_module::DiffModule::new(filename)?;
+
+    println!("==> Results");
+    for entry in result.iter() {
+        diff_mod.treat_entry(entry)?;
+    }
+    diff_mod.summary()?;
+    Ok(())
+}
+
+fn run_near(configuration: &Config, reference: &str, scan_options: &ScanOptions) -> Result<(), Box<dyn Error>> {
+    generate_dump(reference, scan_options)?;
+    generate_dump(&configuration.other_path, scan_options)?;
+    println!("==> Search near duplicates");
+    let reference = load_dump(reference)?;
+    let other = load_dump(&configuration.other_path)?;
+    let result = near_module::find_near_duplicates(&reference, &other, &configuration.near_options);
+
+    println!("==> Results");
+    result.iter().for_each(near_module::display_near_duplicate);
+    Ok(())
 }
 
 pub fn run(configuration: &Config) -> Result<(), Box<dyn Error>> {
+    // Commands on quarantine directory do not compare trees
+    match &configuration.mode {
+        UseMode::Restore(selection) => return Ok(quarantine_module::restore(&configuration.quarantine_dir, selection)?),
+        UseMode::Purge(days) => return Ok(quarantine_module::purge(&configuration.quarantine_dir, *days)?),
+        UseMode::Undo => return Ok(journal::undo(&configuration.journal)?),
+        _ => {}
+    }
     println!(" Reference path: '{}'", configuration.reference_path);
     println!("comparison path: '{}'", configuration.other_path);
 
-    let mut output_mod: Box<dyn OutputModule> = match configuration.mode {
-        UseMode::Print => Box::new(display_module::DisplayModule{}),
-        UseMode::Interactive => Box::new(interactive_module::InteractiveModule{}),
-        UseMode::Batch => Box::new(batch_module::BatchModule::new()),
-    };
+    for (old, new) in configuration.rebases.iter() {
+        rebase_dump(old, new)?;
+    }
 
-    let result = check_directory(&configuration.reference_path);
-    if result.is_err() {
-        return Err(result.err().unwrap().into());
+    if !configuration.reference_dump {
+        let result = check_directory(&configuration.reference_path);
+        if result.is_err() {
+            return Err(result.err().unwrap().into());
+        }
+    }
+    if configuration.mode == UseMode::Export {
+        export_dump(&configuration.reference_path, &configuration.other_path, &configuration.scan_options)?;
+        return Ok(());
     }
     let result = check_directory(&configuration.other_path);
     if result.is_err() {
         return Err(result.err().unwrap().into());
     }
+    // Reference tree is not available, its exported dump is used instead
+    let (reference, scan_options) = if configuration.reference_dump {
+        (import_dump(&configuration.reference_path, &configuration.scan_options)?, configuration.scan_options.clone())
+    } else {
+        (configuration.reference_path.clone(), nested_scan_options(configuration)?)
+    };
 
-    let result = compare_trees(&configuration.reference_path, &configuration.other_path, &mut output_mod)?;
+    let offline_reference = configuration.reference_dump;
+    let mut output_mod: Box<dyn OutputModule> = match configuration.mode {
+        UseMode::Print => Box::new(display_module::DisplayModule{}),
+        UseMode::Interactive => Box::new(interactive_module::InteractiveModule{ offline_reference, journal: journal::Journal::open(&configuration.journal, offline_reference)? }),
+        UseMode::Batch => Box::new(batch_module::BatchModule::new(offline_reference)),
+        UseMode::Report => Box::new(report_module::ReportModule::new("report.ndjson", &reference, &configuration.other_path)),
+        UseMode::Csv => Box::new(csv_module::CsvModule::new("review.csv", &configuration.csv_columns)),
+        UseMode::Link => Box::new(replace_module::ReplaceModule::new(configuration.replace_method, configuration.verify_links, configuration.reflink_fallback)),
+        UseMode::Quarantine => Box::new(quarantine_module::QuarantineModule::new(&configuration.quarantine_dir, &configuration.other_path, offline_reference)?),
+        UseMode::Html => Box::new(html_module::HtmlModule::new("report.html", (&reference, dump_name(&reference)), (&configuration.other_path, dump_name(&configuration.other_path)))),
+        UseMode::Diff | UseMode::Snapshot => return run_diff(configuration, &reference, &scan_options),
+        UseMode::Near => return run_near(configuration, &reference, &scan_options),
+        UseMode::Export | UseMode::Restore(_) | UseMode::Purge(_) | UseMode::Undo => unreachable!()
+    };
+
+    // Pairs approved during review replace the comparison of trees
+    let result = if let Some(plan) = &configuration.plan {
+        csv_module::read_plan(plan, &reference, &configuration.other_path, &scan_options, configuration.reference_dump)?
+    } else if configuration.index_compare {
+        index_compare_trees(&reference, &configuration.other_path, &scan_options, &configuration.size_filter, &mut output_mod)?
+    } else {
+        compare_trees(&reference, &configuration.other_path, &scan_options, &configuration.size_filter, &mut output_mod)?
+    };
 
     println!("==> Results");
-    result.iter().all(|(reference, other)| output_mod.treat_duplicated(reference, other).expect("Error during treat_duplicated"));
+    result.iter().all(|(reference, other)| {
+        if hardlinked(&reference.name, &other.name) {
+            output_mod.treat_hardlinked(reference, other);
+            true
+        } else {
+            output_mod.treat_duplicated(reference, other).expect("Error during treat_duplicated")
+        }
+    });
 
     Ok(())
 }
 
 #[derive(PartialEq)]
 #[derive(Debug)]
+#[derive(Default)]
 pub struct Config {
     reference_path: String,
     other_path: String,
-    mode: UseMode
+    mode: UseMode,
+    scan_options: ScanOptions,
+    near_options: NearOptions,
+    size_filter: SizeFilter,
+    index_compare: bool,
+    exclude_nested: bool,
+    /// Reference path is a dump exported on another machine
+    reference_dump: bool,
+    /// Dumps to move from an old location of a tree to its new one before comparing
+    rebases: Vec<(String, String)>,
+    csv_columns: Vec<CsvColumn>,
+    /// Reviewed CSV whose approved removals are applied
+    plan: Option<String>,
+    replace_method: ReplaceMethod,
+    verify_links: bool,
+    /// Method used when reflinks are not supported, duplicate is kept otherwise
+    reflink_fallback: Option<ReplaceMethod>,
+    quarantine_dir: String,
+    /// Journal of removals done in interactive mode
+    journal: String
 }
 
 impl Config {
@@ -470,26 +1187,226 @@ impl Config {
             Some(value) => value,
             None => return Err("No reference path provided")
         };
+        if ["--restore", "--purge", "--undo"].contains(&reference_path.as_str()) {
+            return Config::build_command(&reference_path, args);
+        }
         let other_path = match args.next() {
             Some(value) => value,
             None => return Err("No other path provided")
         };
-        let mode = match args.next() {
-            Some(value) => if value == "-i" {
-                UseMode::Interactive
+        let mut mode = UseMode::Print;
+        let mut scan_options = ScanOptions::default();
+        let mut near_options = NearOptions::default();
+        let mut size_filter = SizeFilter::default();
+        let mut index_compare = false;
+        let mut exclude_nested = false;
+        let mut reference_dump = false;
+        let mut rebases = Vec::new();
+        let mut csv_columns = csv_module::ALL_COLUMNS.to_vec();
+        let mut plan = None;
+        let mut replace_method = ReplaceMethod::default();
+        let mut verify_links = false;
+        let mut reflink_fallback = None;
+        let mut quarantine_dir = String::from(quarantine_module::DEFAULT_DIRECTORY);
+        let mut journal = String::from(journal::DEFAULT_JOURNAL);
+        while let Some(value) = args.next() {
+            if value == "-i" {
+                mode = UseMode::Interactive;
             }
             else if value == "-p" {
-                UseMode::Print
+                mode = UseMode::Print;
             }
             else if value == "-b" {
-                UseMode::Batch
+                mode = UseMode::Batch;
+            }
+            else if value == "-d" {
+                mode = UseMode::Diff;
+            }
+            else if value == "-s" {
+                mode = UseMode::Snapshot;
+            }
+            else if value == "-n" {
+                mode = UseMode::Near;
+            }
+            else if value == "-e" {
+                mode = UseMode::Export;
+            }
+            else if value == "-j" {
+                mode = UseMode::Report;
+            }
+            else if value == "-c" {
+                mode = UseMode::Csv;
+            }
+            else if value == "-w" {
+                mode = UseMode::Html;
+            }
+            else if value == "-l" {
+                mode = UseMode::Link;
+            }
+            else if value == "-q" {
+                mode = UseMode::Quarantine;
+            }
+            else if value == "--quarantine-dir" {
+                quarantine_dir = parse_value(&mut args, "No directory provided for --quarantine-dir")?;
+            }
+            else if value == "--journal" {
+                journal = parse_value(&mut args, "No file provided for --journal")?;
+            }
+            else if value == "--link-type" {
+                replace_method = match args.next().as_deref() {
+                    Some("hard") => ReplaceMethod::Hardlink,
+                    Some("absolute") => ReplaceMethod::AbsoluteSymlink,
+                    Some("relative") => ReplaceMethod::RelativeSymlink,
+                    Some("reflink") => ReplaceMethod::Reflink,
+                    _ => return Err("Invalid value for --link-type")
+                };
+            }
+            else if value == "--reflink-fallback" {
+                reflink_fallback = match args.next().as_deref() {
+                    Some("none") => None,
+                    Some("hard") => Some(ReplaceMethod::Hardlink),
+                    Some("absolute") => Some(ReplaceMethod::AbsoluteSymlink),
+                    Some("relative") => Some(ReplaceMethod::RelativeSymlink),
+                    _ => return Err("Invalid value for --reflink-fallback")
+                };
+            }
+            else if value == "--verify-links" {
+                verify_links = true;
+            }
+            else if value == "--csv-columns" {
+                let columns = parse_value::<String>(&mut args, "No columns provided for --csv-columns")?;
+                csv_columns = match columns.split(',').map(CsvColumn::from_name).collect::<Result<Vec<CsvColumn>, String>>() {
+                    Ok(columns) => columns,
+                    Err(_) => return Err("Invalid value for --csv-columns")
+                };
+            }
+            else if value == "--plan" {
+                plan = Some(parse_value(&mut args, "No file provided for --plan")?);
+            }
+            else if value == "--reference-dump" {
+                reference_dump = true;
+            }
+            else if value == "--rebase" {
+                let old = parse_value(&mut args, "No old path provided for --rebase")?;
+                let new = parse_value(&mut args, "No new path provided for --rebase")?;
+                rebases.push((old, new));
+            }
+            else if value == "--hash-names" {
+                scan_options.hash_names = true;
+            }
+            else if value == "--hash-types" {
+                scan_options.hash_names = true;
+                scan_options.hash_types = true;
+            }
+            else if value == "--exclude" {
+                scan_options.excludes.push(parse_value(&mut args, "No pattern provided for --exclude")?);
+            }
+            else if value == "--include" {
+                scan_options.includes.push(parse_value(&mut args, "No pattern provided for --include")?);
+            }
+            else if value == "--gitignore" {
+                scan_options.gitignore = true;
+            }
+            else if value == "--one-filesystem" {
+                scan_options.one_filesystem = true;
+            }
+            else if value == "--hash-special" {
+                scan_options.hash_special = true;
+            }
+            else if value == "--follow-links" {
+                scan_options.follow_links = true;
+            }
+            else if value == "--keep-going" {
+                scan_options.keep_going = true;
+            }
+            else if value == "--retries" {
+                scan_options.retries = parse_value(&mut args, "Invalid value for --retries")?;
+            }
+            else if value == "--exclude-nested" {
+                exclude_nested = true;
+            }
+            else if value == "--index" {
+                index_compare = true;
+            }
+            else if value == "--near-min-items" {
+                near_options.min_items = parse_value(&mut args, "Invalid value for --near-min-items")?;
+            }
+            else if value == "--near-threshold" {
+                near_options.threshold = parse_value(&mut args, "Invalid value for --near-threshold")?;
+                if !(0.0..=1.0).contains(&near_options.threshold) {
+                    return Err("--near-threshold must be between 0 and 1");
+                }
+            }
+            else if value == "--near-metric" {
+                near_options.metric = match args.next().as_deref() {
+                    Some("jaccard") => SimilarityMetric::Jaccard,
+                    Some("containment") => SimilarityMetric::Containment,
+                    _ => return Err("Invalid value for --near-metric")
+                };
+            }
+            else if value == "--min-size" {
+                size_filter.min_size = Some(parse_value(&mut args, "Invalid value for --min-size")?);
+            }
+            else if value == "--max-size" {
+                size_filter.max_size = Some(parse_value(&mut args, "Invalid value for --max-size")?);
+            }
+            else if value == "--empty-files" {
+                size_filter.empty_files = match args.next().as_deref() {
+                    Some("report") => EmptyFilePolicy::Report,
+                    Some("ignore") => EmptyFilePolicy::Ignore,
+                    _ => return Err("Invalid value for --empty-files")
+                };
             }
             else {
-                return Err("Error with 3rd argument");
-            },
-            None => UseMode::Print
+                return Err("Unknown argument");
+            }
+        }
+        if reference_dump && mode == UseMode::Export {
+            return Err("--reference-dump can not be used with -e");
+        }
+        // Replacing duplicates needs reference files
+        if reference_dump && mode == UseMode::Link {
+            return Err("--reference-dump can not be used with -l");
+        }
+        if plan.is_some() && ![UseMode::Print, UseMode::Interactive, UseMode::Batch, UseMode::Report, UseMode::Html, UseMode::Link, UseMode::Quarantine].contains(&mode) {
+            return Err("--plan can only be used with -p, -i, -b, -j, -w, -l or -q");
+        }
+        Ok(Config {reference_path, other_path, mode, scan_options, near_options, size_filter, index_compare, exclude_nested, reference_dump, rebases, csv_columns, plan, replace_method, verify_links, reflink_fallback, quarantine_dir, journal})
+    }
+
+    /// --restore <quarantine directory> [paths...], --purge <quarantine directory> --older-than <days> or --undo <journal>
+    fn build_command(command: &str, mut args: impl Iterator <Item = String>) -> Result<Config, & 'static str> {
+        if command == "--undo" {
+            let journal = parse_value(&mut args, "No journal provided")?;
+            if args.next().is_some() {
+                return Err("Unknown argument");
+            }
+            return Ok(Config { mode: UseMode::Undo, journal, ..Config::default() });
+        }
+        let quarantine_dir = match args.next() {
+            Some(value) => value,
+            None => return Err("No quarantine directory provided")
+        };
+        let mode = if command == "--restore" {
+            UseMode::Restore(args.collect())
+        } else {
+            if args.next().as_deref() != Some("--older-than") {
+                return Err("--purge needs --older-than");
+            }
+            let days = parse_value(&mut args, "Invalid value for --older-than")?;
+            if args.next().is_some() {
+                return Err("Unknown argument");
+            }
+            UseMode::Purge(days)
         };
-        Ok(Config {reference_path, other_path, mode})
+        Ok(Config { mode, quarantine_dir, ..Config::default() })
+    }
+}
+
+fn parse_value<T: FromStr>(args: &mut impl Iterator<Item = String>, error: &'static str) -> Result<T, &'static str> {
+    match args.next().map(|v| v.parse::<T>()) {
+        Some(Ok(value)) => Ok(value),
+        _ => Err(error)
     }
 }
 
@@ -505,7 +1422,7 @@ fn compute_file_sha1(file_name: &str) -> Result<sha1::Sha1Key, String> {
         Err(_e) => return Err(format!("Unable to open file {}", file_name))
     };
 
-    let mut data: Vec<u8> = Vec::new();
+    let mut data = Vec::from(EntryKind::File.domain());
     let read_result = file.read_to_end(&mut data);
     if read_result.is_err() {
         return Err(format!("Unable to read content of file {file_name}").to_string());
@@ -514,11 +1431,8 @@ fn compute_file_sha1(file_name: &str) -> Result<sha1::Sha1Key, String> {
     Ok(sha1::compute_sha1(data))
 }
 
+/// Link is hashed by its target path so a dangling link can also be hashed
 fn compute_link_sha1(link_name: &str) -> Result<sha1::Sha1Key, String> {
-    let check = fs::exists(link_name);
-    if check.is_err() || !check.unwrap() {
-        return Err(format!("file {link_name} do not exist").to_string())
-    }
     let read_result = fs::read_link(link_name);
     let path = match read_result {
         Ok(p) => p,
@@ -528,10 +1442,45 @@ fn compute_link_sha1(link_name: &str) -> Result<sha1::Sha1Key, String> {
         Some(str) => str,
         None => return Err(format!("Fail to convert link {link_name} path to string").to_string())
     };
-    let data: Vec<u8> = Vec::from(path_str);
+    let mut data = Vec::from(EntryKind::Link.domain());
+    data.extend(path_str.as_bytes());
     Ok(sha1::compute_sha1(data))
 }
 
+/// Both paths are regular files sharing the same inode
+fn hardlinked(first: &str, second: &str) -> bool {
+    match (fs::symlink_metadata(first), fs::symlink_metadata(second)) {
+        (Ok(a), Ok(b)) => a.is_file() && b.is_file() && a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false
+    }
+}
+
+/// Name of special file type, None for regular files, links and directories
+fn special_type(file_type: &fs::FileType) -> Option<&'static str> {
+    if file_type.is_fifo() {
+        Some("fifo")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_char_device() {
+        Some("char device")
+    } else if file_type.is_block_device() {
+        Some("block device")
+    } else {
+        None
+    }
+}
+
+/// Special files have no content, device nodes are identified by their device number
+fn compute_special_sha1(special_type: &str, rdev: u64) -> sha1::Sha1Key {
+    let mut data = Vec::from(EntryKind::Special.domain());
+    data.extend(special_type.as_bytes());
+    data.push(0);
+    if special_type.ends_with("device") {
+        data.extend(rdev.to_le_bytes());
+    }
+    sha1::compute_sha1(data)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -542,12 +1491,160 @@ mod test {
         let ref_config = Config {
             reference_path: "reference".to_string(),
             other_path: "other".to_string(),
-            mode: UseMode::Print
+            mode: UseMode::Print,
+            scan_options: ScanOptions::default(),
+            near_options: NearOptions::default(),
+            size_filter: SizeFilter::default(),
+            index_compare: false,
+            exclude_nested: false,
+            reference_dump: false,
+            rebases: Vec::new(),
+            csv_columns: csv_module::ALL_COLUMNS.to_vec(),
+            plan: None,
+            replace_method: ReplaceMethod::Hardlink,
+            verify_links: false,
+            reflink_fallback: None,
+            quarantine_dir: "quarantine".to_string(),
+            journal: "compare_tree_journal.tsv".to_string()
         };
         let result = Config::build(args.into_iter()).unwrap();
         assert_eq!(ref_config, result);
     }
     #[test]
+    fn test_parse_diff() {
+        let args = vec!["command".to_string(), "reference".to_string(), "other".to_string(), "-d".to_string()];
+        assert_eq!(UseMode::Diff, Config::build(args.into_iter()).unwrap().mode);
+        let args = vec!["command".to_string(), "reference".to_string(), "other".to_string(), "-j".to_string()];
+        assert_eq!(UseMode::Report, Config::build(args.into_iter()).unwrap().mode);
+        let args = vec!["command".to_string(), "reference".to_string(), "other".to_string(), "-w".to_string()];
+        assert_eq!(UseMode::Html, Config::build(args.into_iter()).unwrap().mode);
+        let args = vec!["command".to_string(), "reference".to_string(), "other".to_string(), "-l".to_string()];
+        assert_eq!(UseMode::Link, Config::build(args.into_iter()).unwrap().mode);
+        let args = vec!["command".to_string(), "reference".to_string(), "other".to_string(), "-q".to_string(), "--quarantine-dir".to_string(), "trash".to_string()];
+        let result = Config::build(args.into_iter()).unwrap();
+        assert_eq!((UseMode::Quarantine, "trash"), (result.mode, result.quarantine_dir.as_str()));
+    }
+    #[test]
+    fn test_parse_quarantine_commands() {
+        let build = |args: &[&str]| Config::build(args.iter().map(|v| v.to_string()));
+        let result = build(&["command", "--restore", "trash", "oth/a", "oth/b"]).unwrap();
+        assert_eq!((UseMode::Restore(vec!("oth/a".to_string(), "oth/b".to_string())), "trash"), (result.mode, result.quarantine_dir.as_str()));
+        assert_eq!(UseMode::Restore(Vec::new()), build(&["command", "--restore", "trash"]).unwrap().mode);
+        assert_eq!(UseMode::Purge(30), build(&["command", "--purge", "trash", "--older-than", "30"]).unwrap().mode);
+        assert!(build(&["command", "--purge", "trash"]).is_err());
+        assert!(build(&["command", "--purge", "trash", "--older-than", "soon"]).is_err());
+        assert!(build(&["command", "--restore"]).is_err());
+        let result = build(&["command", "--undo", "journal.tsv"]).unwrap();
+        assert_eq!((UseMode::Undo, "journal.tsv"), (result.mode, result.journal.as_str()));
+        assert!(build(&["command", "--undo"]).is_err());
+        // Directories named like commands are compared as any other directory
+        for name in ["restore", "purge", "undo"] {
+            let result = build(&["command", name, "other"]).unwrap();
+            assert_eq!((UseMode::Print, name, "other"), (result.mode, result.reference_path.as_str(), result.other_path.as_str()));
+        }
+        let result = build(&["command", "reference", "other", "-i", "--journal", "removed.tsv"]).unwrap();
+        assert_eq!((UseMode::Interactive, "removed.tsv"), (result.mode, result.journal.as_str()));
+    }
+    #[test]
+    fn test_parse_hash_options() {
+        let args = vec!["command".to_string(), "reference".to_string(), "other".to_string(), "--hash-types".to_string(), "-b".to_string()];
+        let result = Config::build(args.into_iter()).unwrap();
+        assert_eq!(UseMode::Batch, result.mode);
+        assert_eq!(ScanOptions { hash_names: true, hash_types: true, ..ScanOptions::default() }, result.scan_options);
+    }
+    #[test]
+    fn test_parse_near_options() {
+        let args = vec!["command", "reference", "other", "-n", "--near-min-items", "3", "--near-threshold", "0.5", "--near-metric", "containment"];
+        let result = Config::build(args.into_iter().map(String::from)).unwrap();
+        assert_eq!(UseMode::Near, result.mode);
+        assert_eq!(NearOptions { min_items: 3, threshold: 0.5, metric: SimilarityMetric::Containment }, result.near_options);
+        let args = vec!["command", "reference", "other", "--near-threshold", "high"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+        for threshold in ["-0.1", "1.5", "NaN"] {
+            let args = vec!["command", "reference", "other", "--near-threshold", threshold];
+            assert!(Config::build(args.into_iter().map(String::from)).is_err());
+        }
+    }
+    #[test]
+    fn test_parse_filters() {
+        let args = vec!["command", "reference", "other", "--exclude", ".git/", "--include", "*.jpg", "--exclude", "*.swp", "--gitignore"];
+        let result = Config::build(args.into_iter().map(String::from)).unwrap();
+        assert_eq!(vec!(".git/".to_string(), "*.swp".to_string()), result.scan_options.excludes);
+        assert_eq!(vec!("*.jpg".to_string()), result.scan_options.includes);
+        assert!(result.scan_options.gitignore);
+        let args = vec!["command", "reference", "other", "--exclude"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+    }
+    #[test]
+    fn test_parse_filesystem_options() {
+        let args = vec!["command", "reference", "other", "--one-filesystem", "--hash-special"];
+        let result = Config::build(args.into_iter().map(String::from)).unwrap();
+        assert!(result.scan_options.one_filesystem);
+        assert!(result.scan_options.hash_special);
+        let args = vec!["command", "reference", "other", "--follow-links"];
+        assert!(Config::build(args.into_iter().map(String::from)).unwrap().scan_options.follow_links);
+        let args = vec!["command", "reference", "other", "--keep-going"];
+        assert!(Config::build(args.into_iter().map(String::from)).unwrap().scan_options.keep_going);
+        let args = vec!["command", "reference", "other", "--retries", "5"];
+        assert_eq!(5, Config::build(args.into_iter().map(String::from)).unwrap().scan_options.retries);
+    }
+    #[test]
+    fn test_parse_reference_dump() {
+        let args = vec!["command", "reference", "export.txt", "-e"];
+        assert_eq!(UseMode::Export, Config::build(args.into_iter().map(String::from)).unwrap().mode);
+        let args = vec!["command", "export.txt", "other", "--reference-dump", "-b"];
+        assert!(Config::build(args.into_iter().map(String::from)).unwrap().reference_dump);
+        let args = vec!["command", "reference", "export.txt", "-e", "--reference-dump"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+        let args = vec!["command", "export.txt", "other", "--reference-dump", "-l"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+    }
+    #[test]
+    fn test_parse_csv_options() {
+        let args = vec!["command", "reference", "other", "-c", "--csv-columns", "other,size,decision"];
+        let result = Config::build(args.into_iter().map(String::from)).unwrap();
+        assert_eq!(UseMode::Csv, result.mode);
+        assert_eq!(vec!(CsvColumn::Other, CsvColumn::Size, CsvColumn::Decision), result.csv_columns);
+        let args = vec!["command", "reference", "other", "-c", "--csv-columns", "other,owner"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+        let args = vec!["command", "reference", "other", "-b", "--plan", "review.csv"];
+        assert_eq!(Some("review.csv".to_string()), Config::build(args.into_iter().map(String::from)).unwrap().plan);
+        let args = vec!["command", "reference", "other", "-c", "--plan", "review.csv"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+    }
+    #[test]
+    fn test_parse_link_options() {
+        let args = vec!["command", "reference", "other", "-l", "--link-type", "relative", "--verify-links"];
+        let result = Config::build(args.into_iter().map(String::from)).unwrap();
+        assert_eq!(ReplaceMethod::RelativeSymlink, result.replace_method);
+        assert!(result.verify_links);
+        let args = vec!["command", "reference", "other", "-l", "--link-type", "absolute"];
+        assert_eq!(ReplaceMethod::AbsoluteSymlink, Config::build(args.into_iter().map(String::from)).unwrap().replace_method);
+        let args = vec!["command", "reference", "other", "-l", "--link-type", "soft"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+        let args = vec!["command", "reference", "other", "-l", "--link-type", "reflink", "--reflink-fallback", "relative"];
+        let result = Config::build(args.into_iter().map(String::from)).unwrap();
+        assert_eq!((ReplaceMethod::Reflink, Some(ReplaceMethod::RelativeSymlink)), (result.replace_method, result.reflink_fallback));
+        let args = vec!["command", "reference", "other", "-l", "--reflink-fallback", "reflink"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+    }
+    #[test]
+    fn test_parse_rebase() {
+        let args = vec!["command", "/mnt/new", "other", "--rebase", "/mnt/old", "/mnt/new", "--rebase", "a", "b"];
+        assert_eq!(vec!(("/mnt/old".to_string(), "/mnt/new".to_string()), ("a".to_string(), "b".to_string())),
+                   Config::build(args.into_iter().map(String::from)).unwrap().rebases);
+        let args = vec!["command", "reference", "other", "--rebase", "/mnt/old"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+    }
+    #[test]
+    fn test_parse_size_filter() {
+        let args = vec!["command", "reference", "other", "--min-size", "10", "--max-size", "4096", "--empty-files", "ignore"];
+        let result = Config::build(args.into_iter().map(String::from)).unwrap();
+        assert_eq!(SizeFilter { min_size: Some(10), max_size: Some(4096), empty_files: EmptyFilePolicy::Ignore }, result.size_filter);
+        let args = vec!["command", "reference", "other", "--empty-files", "keep"];
+        assert!(Config::build(args.into_iter().map(String::from)).is_err());
+    }
+    #[test]
     fn test_parse_fail() {
         let args = vec!["reference".to_string(), "other".to_string()];
         assert!(Config::build(args.into_iter()).is_err());
@@ -564,7 +1661,7 @@ mod test {
     fn analyse_empty_dir(name: &str) -> filetree_info::FileTreeInfo {
         let create_result = fs::create_dir(name);
         assert!(create_result.is_ok());
-        let analyse_result = analyse(name);
+        let analyse_result = analyse(name, &ScanOptions::default());
         let rm_result = fs::remove_dir(name);
         assert!(rm_result.is_ok());
         assert!(analyse_result.is_ok());
@@ -576,9 +1673,12 @@ mod test {
     fn test_check_analyse_empty_dir() {
         let my_info = filetree_info::FileTreeInfo {
             name: "empty".to_string(),
+            kind: EntryKind::Directory,
             height: 1,
-            sha1: sha1::compute_sha1(vec!(0,0,0,0)),
-            nb_item: 0
+            sha1: sha1::compute_sha1(vec!(b'd', b'i', b'r', 0, 0, 0, 0, 0)),
+            nb_item: 0,
+            size: 0,
+            inode: None
         };
         assert_eq!(my_info, analyse_empty_dir("empty"));
     }
@@ -599,7 +1699,7 @@ mod test {
             assert!(file2.write_all(b"Hello world!").is_ok());
             assert!(fs::create_dir("other/empty").is_ok());
         }
-        assert!(&analyse("reference").expect("Error with reference").equivalent(&analyse("other").expect("Error with other")));
+        assert!(&analyse("reference", &ScanOptions::default()).expect("Error with reference").equivalent(&analyse("other", &ScanOptions::default()).expect("Error with other")));
         assert!(fs::remove_dir_all("reference").is_ok());
         assert!(fs::remove_dir_all("other").is_ok());
         assert!(fs::remove_file(dump_name("reference")).is_ok());
@@ -618,11 +1718,51 @@ mod test {
             let mut file3 = File::create("reference2/file3.txt").expect("Unable to create file3");
             assert!(file3.write_all(b"Hello world!").is_ok());
         }
-        assert_eq!(3, analyse("reference2").expect("Error with reference").height);
+        assert_eq!(3, analyse("reference2", &ScanOptions::default()).expect("Error with reference").height);
         assert!(fs::remove_dir_all("reference2").is_ok());
         assert!(fs::remove_file(dump_name("reference2")).is_ok());
     }
     #[test]
+    fn test_hash_names() {
+        create_filetree("reference3", vec!(("a.txt".to_string(), "First content".to_string()),
+                                          ("b.txt".to_string(), "Second content".to_string())));
+        create_filetree("other3", vec!(("a.txt".to_string(), "Second content".to_string()),
+                                      ("b.txt".to_string(), "First content".to_string())));
+        let content_options = ScanOptions::default();
+        let name_options = ScanOptions { hash_names: true, ..ScanOptions::default() };
+        assert!(analyse("reference3", &content_options).expect("Error with reference").equivalent(&analyse("other3", &content_options).expect("Error with other")));
+        assert!(!analyse("reference3", &name_options).expect("Error with reference").equivalent(&analyse("other3", &name_options).expect("Error with other")));
+        assert_eq!(name_options.header(), read_dump_header("reference3").expect("Error when reading header"));
+        assert!(fs::remove_dir_all("reference3").is_ok());
+        assert!(fs::remove_dir_all("other3").is_ok());
+        assert!(fs::remove_file(dump_name("reference3")).is_ok());
+        assert!(fs::remove_file(dump_name("other3")).is_ok());
+    }
+    #[test]
+    fn test_filters() {
+        create_filetree("reference4", vec!(("photos/a.jpg".to_string(), "First photo".to_string()),
+                                          ("photos/b.jpg".to_string(), "Second photo".to_string())));
+        create_filetree("other4", vec!(("photos/a.jpg".to_string(), "First photo".to_string()),
+                                      ("photos/b.jpg".to_string(), "Second photo".to_string()),
+                                      ("photos/Thumbs.db".to_string(), "Thumbnails".to_string()),
+                                      ("photos/.a.jpg.swp".to_string(), "Swap file".to_string()),
+                                      ("photos/.git/HEAD".to_string(), "ref: refs/heads/master".to_string()),
+                                      ("photos/.compare_tree_ignore".to_string(), "*.swp\n# Comment\n.git/\n".to_string())));
+        // Ignore file itself is not hashed
+        let options = ScanOptions { excludes: vec!("Thumbs.db".to_string()), ..ScanOptions::default() };
+        let reference = analyse("reference4", &options).expect("Error with reference");
+        let other = analyse("other4", &options).expect("Error with other");
+        assert!(reference.equivalent(&other));
+        assert_eq!(2, other.nb_item);
+        assert!(!analyse("other4", &ScanOptions::default()).expect("Error with other").equivalent(&reference));
+        let options = ScanOptions { includes: vec!("a.jpg".to_string()), ..ScanOptions::default() };
+        assert_eq!(1, analyse("other4", &options).expect("Error with other").nb_item);
+        assert!(fs::remove_dir_all("reference4").is_ok());
+        assert!(fs::remove_dir_all("other4").is_ok());
+        assert!(fs::remove_file(dump_name("reference4")).is_ok());
+        assert!(fs::remove_file(dump_name("other4")).is_ok());
+    }
+    #[test]
     fn test_compare_no_common1() {
         compare_generic("ref_dump1.txt", vec!(("toto".to_string(), "0000000400000003000000020000000100000000".to_string())),
                         "oth_dump1.txt", vec!(("tutu".to_string(), "0000000F00000003000000020000000100000000".to_string())),
@@ -644,8 +1784,11 @@ mod test {
             let mut ref_dump = File::create(ref_name).expect("Unable to create ref dump");
             ref_dump.write(format!("{}\n",
                                    filetree_info::FileTreeInfo{name: "toto".to_string(),
+                                                               kind: EntryKind::File,
                                                                height: 0,
                                                                nb_item: 0,
+                                                               size: 0,
+                                                               inode: None,
                                                                sha1: sha1::Sha1Key::from_string("0000000400000003000000020000000100000000").expect("From_string error")
                                                               }
                                    ).as_bytes()
@@ -653,8 +1796,11 @@ mod test {
             let mut other_dump = File::create(other_name).expect("Unable to create other dump");
             other_dump.write(format!("{}\n",
                                    filetree_info::FileTreeInfo{name: "tutu".to_string(),
+                                                               kind: EntryKind::File,
                                                                height: 0,
                                                                nb_item: 2,
+                                                               size: 0,
+                                                               inode: None,
                                                                sha1: sha1::Sha1Key::from_string("0000000400000003000000020000000100000000").expect("From_string error")
                                                               }
                                    ).as_bytes()
@@ -664,19 +1810,26 @@ mod test {
         let ref_bufreader = BufReader::new(ref_dump);
         let other_dump = File::open(other_name).expect("Unable to open other dump");
         let other_bufreader = BufReader::new(other_dump);
-        let mut to_remove = Vec::<(String, String)>::new();
-        compare_iter(ref_bufreader.lines(), other_bufreader.lines(), &mut to_remove).expect("Error during comparison");
-        assert_eq!(Vec::<(String, String)>::new(), to_remove);
+        let mut to_remove = Vec::new();
+        compare_iter(ref_bufreader.lines(), other_bufreader.lines(), &SizeFilter::default(), false, &mut to_remove).expect("Error during comparison");
+        assert_eq!(Vec::<(String, String)>::new(), pair_names(to_remove));
         assert!(fs::remove_file(ref_name).is_ok());
         assert!(fs::remove_file(other_name).is_ok());
     }
+    /// Names of duplicated entries
+    fn pair_names(result: Vec<(filetree_info::FileTreeInfo, filetree_info::FileTreeInfo)>) -> Vec<(String, String)> {
+        result.into_iter().map(|(reference, other)| (reference.name, other.name)).collect()
+    }
     fn create_dump(name: &str, list: Vec::<(String, String)>) {
             let mut dump = File::create(name).expect("Unable to create dump");
             list.iter().for_each(|(item_name, sha1)|{
                                  dump.write(format!("{}\n",
                                                     filetree_info::FileTreeInfo{name: item_name.to_string(),
+                                                                                kind: EntryKind::File,
                                                                                 height: 0,
                                                                                 nb_item: 0,
+                                                                                size: 0,
+                                                                                inode: None,
                                                                                 sha1: sha1::Sha1Key::from_string(sha1).expect("From_string error")
                                                                                }
                                                    ).as_bytes()
@@ -693,9 +1846,9 @@ mod test {
         let oth_dump = File::open(oth_name).expect("Unable to open other dump");
         let ref_bufreader = BufReader::new(ref_dump);
         let oth_bufreader = BufReader::new(oth_dump);
-        let mut to_remove = Vec::<(String, String)>::new();
-        compare_iter(ref_bufreader.lines(), oth_bufreader.lines(), &mut to_remove).expect("Error during comparison");
-        assert_eq!(ref_to_remove, to_remove);
+        let mut to_remove = Vec::new();
+        compare_iter(ref_bufreader.lines(), oth_bufreader.lines(), &SizeFilter::default(), false, &mut to_remove).expect("Error during comparison");
+        assert_eq!(ref_to_remove, pair_names(to_remove));
         assert!(fs::remove_file(ref_name).is_ok());
         assert!(fs::remove_file(oth_name).is_ok());
     }
@@ -822,7 +1975,7 @@ mod test {
         let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
         assert_eq!(vec!(("ref3/dummy_dir1/dummy_dur2".to_string(), "oth3/similar".to_string()),
                         ("ref3/dummy_dir1/dummy_dur2/test2.txt".to_string(), "oth3/c.txt".to_string())
-                       ), compare_trees(ref_name, oth_name, &mut output_mod).expect("Error during comparison"));
+                       ), pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
         assert!(fs::remove_dir_all(ref_name).is_ok());
         assert!(fs::remove_dir_all(oth_name).is_ok());
         assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
@@ -843,7 +1996,7 @@ mod test {
                                        ("c.txt".to_string(), "This is a an other dummy file".to_string()),
                                       ));
         let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
-        assert_eq!(vec!(("ref4/dummy_dir1".to_string(), "oth4".to_string())), compare_trees(ref_name, oth_name, &mut output_mod).expect("Error during comparison"));
+        assert_eq!(vec!(("ref4/dummy_dir1".to_string(), "oth4".to_string())), pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
         assert!(fs::remove_dir_all(ref_name).is_ok());
         assert!(fs::remove_dir_all(oth_name).is_ok());
         assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
@@ -866,7 +2019,7 @@ mod test {
                                        ("similar_bis/b.txt".to_string(), "This is a an other dummy file".to_string()),
                                       ));
         let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
-        assert_eq!(vec!(("ref5/dummy_dir1".to_string(), "oth5/dir".to_string()), ("ref5/dummy_dir1/dummy_dur2".to_string(), "oth5/similar_bis".to_string())), compare_trees(ref_name, oth_name, &mut output_mod).expect("Error during comparison"));
+        assert_eq!(vec!(("ref5/dummy_dir1".to_string(), "oth5/dir".to_string()), ("ref5/dummy_dir1/dummy_dur2".to_string(), "oth5/similar_bis".to_string())), pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
         assert!(fs::remove_dir_all(ref_name).is_ok());
         assert!(fs::remove_dir_all(oth_name).is_ok());
         assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
@@ -892,11 +2045,514 @@ mod test {
                                       ));
         let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
         assert_eq!(vec!(("ref6/dummy_dir1/dummy_dur2".to_string(), "oth6/similar_bis".to_string()),
-                        ("ref6/dummy_dir1/dummy_dur2/test2.txt".to_string(), "oth6/dir/similar/b.txt".to_string()),
                         ("ref6/dummy_dir1/c.txt".to_string(), "oth6/dir/c.txt".to_string()),
                         ("ref6/dummy_dir1/break.txt".to_string(), "oth6/dir/similar/disturb.txt".to_string()),
-                        ("ref6/dummy_dir1/dummy_dur2/test.txt".to_string(), "oth6/dir/similar/a.txt".to_string())
-                        ), compare_trees(ref_name, oth_name, &mut output_mod).expect("Error during comparison"));
+                        ("ref6/dummy_dir1/dummy_dur2/test.txt".to_string(), "oth6/dir/similar/a.txt".to_string()),
+                        ("ref6/dummy_dir1/dummy_dur2/test2.txt".to_string(), "oth6/dir/similar/b.txt".to_string())
+                        ), pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    #[test]
+    fn test_index_compare_trees() {
+        let ref_name = "ref11";
+        let oth_name = "oth11";
+        create_filetree(ref_name, vec!(("dummy_dir1/dummy_dur2/test.txt".to_string(), "This is a dummy file".to_string()),
+                                       ("dummy_dir1/dummy_dur2/test2.txt".to_string(), "This is a an other dummy file".to_string()),
+                                       ("dummy_dir1/c.txt".to_string(), "This is a an other dummy file".to_string()),
+                                       ("d.txt".to_string(), "Only in reference".to_string()),
+                                      ));
+        create_filetree(oth_name, vec!(("a/b/c/d/e/similar/a.txt".to_string(), "This is a dummy file".to_string()),
+                                       ("a/b/c/d/e/similar/b.txt".to_string(), "This is a an other dummy file".to_string()),
+                                       ("a/b/c/d/e/f/g/h/c.txt".to_string(), "This is a an other dummy file".to_string()),
+                                       ("a/b/c/d/e/f/g/h/i/j/d.txt".to_string(), "Only in reference".to_string()),
+                                       ("a/b/c/d/e/f/g/h/i/j/k.txt".to_string(), "Only in other".to_string()),
+                                      ));
+        let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
+        let mut result = pair_names(index_compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison"));
+        result.sort();
+        // Reference height is 3 while oth11/a/b/c/d/e/f has height 5, its leaves and its smaller subtrees are still matched
+        let reference = load_dump(ref_name).expect("Unable to load dump");
+        let other = load_dump(oth_name).expect("Unable to load dump");
+        let height = |tree: &dump_tree::DumpTree, name: &str| tree.entries.iter().find(|e| e.name == name).expect("Entry not found").height;
+        assert_eq!(3, height(&reference, ref_name));
+        assert_eq!(5, height(&other, "oth11/a/b/c/d/e/f"));
+        assert_eq!(vec!(("ref11/d.txt".to_string(), "oth11/a/b/c/d/e/f/g/h/i/j/d.txt".to_string()),
+                        ("ref11/dummy_dir1/c.txt".to_string(), "oth11/a/b/c/d/e/f/g/h/c.txt".to_string()),
+                        ("ref11/dummy_dir1/dummy_dur2".to_string(), "oth11/a/b/c/d/e/similar".to_string())
+                       ), result);
+        // Entries sharing a SHA1 always have the same height since directory hashes include the hashes of their
+        // sub-directories, so comparing split dumps of every height down to 0 finds the same pairs
+        let mut expected = pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison"));
+        expected.sort();
+        assert_eq!(expected, result);
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    #[test]
+    fn test_link_never_match_file() {
+        let ref_name = "ref9";
+        let oth_name = "oth9";
+        create_filetree(ref_name, vec!(("dir/target".to_string(), "other.txt".to_string()),));
+        create_filetree(oth_name, vec!(("dir/other.txt".to_string(), "Some content".to_string()),));
+        std::os::unix::fs::symlink("other.txt", "oth9/dir/target").expect("Unable to create link");
+        let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
+        assert_eq!(Vec::<(String, String)>::new(), pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    #[test]
+    fn test_size_filter() {
+        let ref_name = "ref12";
+        let oth_name = "oth12";
+        create_filetree(ref_name, vec!(("small/a".to_string(), "x".to_string()),
+                                       ("small/c".to_string(), "yy".to_string()),
+                                       ("big/b.txt".to_string(), "Large enough content".to_string()),
+                                       ("markers/empty".to_string(), "".to_string())));
+        create_filetree(oth_name, vec!(("small2/a".to_string(), "x".to_string()),
+                                       ("small2/c".to_string(), "yy".to_string()),
+                                       ("copy_a".to_string(), "x".to_string()),
+                                       ("copy_b.txt".to_string(), "Large enough content".to_string()),
+                                       ("copy_empty".to_string(), "".to_string())));
+        let size_filter = SizeFilter { min_size: Some(5), max_size: None, empty_files: EmptyFilePolicy::Ignore };
+        let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
+        let mut result = pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &size_filter, &mut output_mod).expect("Error during comparison"));
+        result.sort();
+        // Small files still make their directories equivalent
+        assert_eq!(vec!(("ref12/big/b.txt".to_string(), "oth12/copy_b.txt".to_string()),
+                        ("ref12/small".to_string(), "oth12/small2".to_string())), result);
+        let mut result = pair_names(index_compare_trees(ref_name, oth_name, &ScanOptions::default(), &size_filter, &mut output_mod).expect("Error during comparison"));
+        result.sort();
+        assert_eq!(2, result.len());
+        let tree = load_dump(ref_name).expect("Unable to load dump");
+        assert_eq!(23, tree.entries[tree.root()].size);
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    #[test]
+    fn test_special_files() {
+        let root = "special13";
+        create_filetree(root, vec!(("a.txt".to_string(), "Some content".to_string()),));
+        let _socket = std::os::unix::net::UnixListener::bind("special13/socket").expect("Unable to create socket");
+        let scan = |options: &ScanOptions| {
+            let mut filter = filter::Filter::new(root, options);
+            let mut context = ScanContext::new(root).expect("Unable to create context");
+            let mut output = Vec::new();
+            let result = analyse_filetree(PathBuf::from(root), options, &mut filter, &mut context, &mut output).expect("Error during analyse");
+            (result, context.summary, String::from_utf8(output).expect("Invalid dump"))
+        };
+        let (skipped, summary, dump) = scan(&ScanOptions::default());
+        assert_eq!(1, skipped.nb_item);
+        assert_eq!(1, summary.nb_special);
+        assert!(!dump.contains("socket"));
+        let (hashed, summary, dump) = scan(&ScanOptions { hash_special: true, ..ScanOptions::default() });
+        assert_eq!(2, hashed.nb_item);
+        assert_eq!(1, summary.nb_special);
+        assert!(!hashed.equivalent(&skipped));
+        let line = dump.lines().find(|l| l.contains("socket")).expect("Socket not in dump");
+        assert_eq!(EntryKind::Special, filetree_info::FileTreeInfo::from(line).expect("Invalid line").kind);
+        let (_, summary, _) = scan(&ScanOptions { one_filesystem: true, ..ScanOptions::default() });
+        assert!(summary.skipped_mount_points.is_empty());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+    #[test]
+    fn test_follow_links() {
+        let root = "links14";
+        create_filetree(root, vec!(("a.txt".to_string(), "Some content".to_string()),
+                                   ("sub/b.txt".to_string(), "Other content".to_string())));
+        std::os::unix::fs::symlink("a.txt", "links14/to_file").expect("Unable to create link");
+        std::os::unix::fs::symlink("sub", "links14/to_dir").expect("Unable to create link");
+        std::os::unix::fs::symlink("..", "links14/sub/loop").expect("Unable to create link");
+        std::os::unix::fs::symlink("missing", "links14/broken").expect("Unable to create link");
+        let scan = |options: &ScanOptions| {
+            let mut filter = filter::Filter::new(root, options);
+            let mut context = ScanContext::new(root).expect("Unable to create context");
+            let mut output = Vec::new();
+            analyse_filetree(PathBuf::from(root), options, &mut filter, &mut context, &mut output).expect("Error during analyse");
+            let entries = String::from_utf8(output).expect("Invalid dump").lines().map(|l| filetree_info::FileTreeInfo::from(l).expect("Invalid line")).collect::<Vec<_>>();
+            (entries, context.summary)
+        };
+        let find = |entries: &Vec<filetree_info::FileTreeInfo>, name: &str| entries.iter().find(|e| e.name == name).expect("Entry not found").clone();
+
+        let (entries, summary) = scan(&ScanOptions::default());
+        assert_eq!(vec!("links14/broken".to_string()), summary.dangling_links);
+        assert_eq!(EntryKind::Link, find(&entries, "to_dir").kind);
+
+        let (entries, summary) = scan(&ScanOptions { follow_links: true, ..ScanOptions::default() });
+        assert_eq!(vec!("links14/broken".to_string()), summary.dangling_links);
+        assert_eq!(2, summary.link_loops.len());
+        assert_eq!(EntryKind::Link, find(&entries, "broken").kind);
+        let to_file = find(&entries, "to_file");
+        assert!(to_file.equivalent(&find(&entries, "a.txt")));
+        assert_eq!(12, to_file.size);
+        assert!(find(&entries, "to_dir").equivalent(&find(&entries, "sub")));
+        assert_eq!(EntryKind::Link, find(&entries, "to_dir/loop").kind);
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+    #[test]
+    fn test_keep_going() {
+        let root = "errors16";
+        create_filetree(root, vec!(("a.txt".to_string(), "Some content".to_string()),
+                                   ("sub/b.txt".to_string(), "Other content".to_string())));
+        // Ignore file which is not valid UTF-8 cannot be read
+        fs::write("errors16/sub/.compare_tree_ignore", [0xff, 0xfe]).expect("Unable to create ignore file");
+        let scan = |options: &ScanOptions| {
+            let mut filter = filter::Filter::new(root, options);
+            let mut context = ScanContext::new(root).expect("Unable to create context");
+            let mut output = Vec::new();
+            let result = analyse_filetree(PathBuf::from(root), options, &mut filter, &mut context, &mut output);
+            (result, context.summary, String::from_utf8(output).expect("Invalid dump"))
+        };
+        assert!(scan(&ScanOptions::default()).0.is_err());
+        let (result, summary, dump) = scan(&ScanOptions { keep_going: true, ..ScanOptions::default() });
+        let result = result.expect("Error during analyse");
+        assert_eq!(EntryKind::Incomplete, result.kind);
+        assert_eq!(2, result.nb_item);
+        assert!(!result.equivalent(&result.clone()));
+        assert_eq!(1, summary.errors.len());
+        assert_eq!("errors16/sub", summary.errors[0].0);
+        assert!(dump.contains("# error sub : Unable to read ignore file"));
+        let tree = dump_tree::DumpTree::from_entries(dump.lines().filter(|l| !l.starts_with('#')).map(|l| filetree_info::FileTreeInfo::from(l).expect("Invalid line")).collect());
+        let error = tree.entries.iter().find(|e| e.name == "sub").expect("Error entry not found");
+        assert_eq!(EntryKind::Error, error.kind);
+        assert!(!error.equivalent(error));
+        let file = tree.entries.iter().find(|e| e.name == "a.txt").expect("File not found");
+        assert!(file.equivalent(file));
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+    #[test]
+    fn test_unstable_files() {
+        let root = "unstable17";
+        create_filetree(root, vec!(("a.txt".to_string(), "Some content".to_string()),));
+        // Metadata collected before a modification simulates a file modified while hashed
+        let stale = fs::metadata("unstable17/a.txt").expect("Unable to get metadata");
+        fs::write("unstable17/a.txt", "Modified content").expect("Unable to modify file");
+        assert!(!unchanged(&stale, &fs::metadata("unstable17/a.txt").expect("Unable to get metadata")));
+        let mut context = ScanContext::new(root).expect("Unable to create context");
+        let result = hash_file("unstable17/a.txt", &stale, &ScanOptions { retries: 0, ..ScanOptions::default() }, &mut context).expect("Error during hash");
+        assert_eq!(EntryKind::Unstable, result.kind);
+        assert!(!result.equivalent(&result.clone()));
+        assert_eq!(vec!("unstable17/a.txt".to_string()), context.summary.unstable);
+        let mut context = ScanContext::new(root).expect("Unable to create context");
+        let result = hash_file("unstable17/a.txt", &stale, &ScanOptions { retries: 1, ..ScanOptions::default() }, &mut context).expect("Error during hash");
+        assert_eq!(EntryKind::File, result.kind);
+        assert_eq!(16, result.size);
+        assert_eq!(compute_file_sha1("unstable17/a.txt").expect("Error during hash"), result.sha1);
+        assert!(context.summary.unstable.is_empty());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+    #[test]
+    fn test_nested_trees() {
+        let outer = "nested18";
+        let nested = "nested18/ref";
+        create_filetree(outer, vec!(("a.txt".to_string(), "Some content".to_string()),
+                                    ("c.txt".to_string(), "Other content".to_string()),
+                                    ("ref/b.txt".to_string(), "Some content".to_string())));
+        assert_eq!(Overlap::ReferenceInOther(PathBuf::from("ref")), check_overlap(nested, outer).expect("Error during check"));
+        assert_eq!(Overlap::OtherInReference(PathBuf::from("ref")), check_overlap(outer, nested).expect("Error during check"));
+        assert_eq!(Overlap::Disjoint, check_overlap(nested, "src").expect("Error during check"));
+        assert!(check_overlap(outer, "nested18/ref/..").is_err());
+        let args = vec!["command", nested, outer];
+        assert!(nested_scan_options(&Config::build(args.into_iter().map(String::from)).unwrap()).is_err());
+        let args = vec!["command", nested, outer, "--exclude-nested"];
+        let options = nested_scan_options(&Config::build(args.into_iter().map(String::from)).unwrap()).expect("Error during check");
+        let canonical_nested = fs::canonicalize(nested).expect("Unable to canonicalise").to_str().expect("to_str() issue").to_string();
+        assert_eq!(vec!(canonical_nested.clone()), options.excluded_paths);
+        let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
+        assert_eq!(vec!(("nested18/ref/b.txt".to_string(), "nested18/a.txt".to_string())),
+                   pair_names(compare_trees(nested, outer, &options, &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
+        // Exclusion only concerns the outer tree so the dump of the nested one can be reused by other comparisons
+        assert_eq!(ScanOptions::default().header(), read_dump_header(nested).expect("Error when reading header"));
+        assert_eq!(options.header(), read_dump_header(outer).expect("Error when reading header"));
+        // Nested directory reached through a followed link of the outer tree is excluded too
+        std::os::unix::fs::symlink("ref", "nested18/to_ref").expect("Unable to create link");
+        let args = vec!["command", nested, outer, "--exclude-nested", "--follow-links"];
+        let options = nested_scan_options(&Config::build(args.into_iter().map(String::from)).unwrap()).expect("Error during check");
+        assert_eq!(2, analyse(outer, &options).expect("Error during analyse").nb_item);
+        assert_eq!(1, analyse(nested, &options).expect("Error during analyse").nb_item);
+        // Disjoint trees are excluded from each other when links are followed
+        create_filetree("nested18_link", vec!(("d.txt".to_string(), "Some content".to_string()),));
+        std::os::unix::fs::symlink("../nested18/ref", "nested18_link/to_ref").expect("Unable to create link");
+        let args = vec!["command", nested, "nested18_link", "--follow-links"];
+        let options = nested_scan_options(&Config::build(args.into_iter().map(String::from)).unwrap()).expect("Error during check");
+        assert!(options.excluded_paths.contains(&canonical_nested));
+        assert_eq!(1, analyse("nested18_link", &options).expect("Error during analyse").nb_item);
+        assert!(fs::remove_dir_all("nested18_link").is_ok());
+        assert!(fs::remove_file(dump_name("nested18_link")).is_ok());
+        assert!(fs::remove_dir_all(outer).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(nested)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(outer)).is_ok());
+        assert!(fs::remove_file(dump_name(nested)).is_ok());
+        assert!(fs::remove_file(dump_name(outer)).is_ok());
+    }
+    #[test]
+    fn test_reference_dump() {
+        let ref_name = "ref19";
+        let oth_name = "oth19";
+        let export_name = "ref19_export.txt";
+        create_filetree(ref_name, vec!(("a.txt".to_string(), "Some content".to_string()),
+                                       ("sub/b.txt".to_string(), "Other content".to_string())));
+        create_filetree(oth_name, vec!(("copy/b.txt".to_string(), "Other content".to_string()),
+                                       ("c.txt".to_string(), "Only in other".to_string())));
+        let options = ScanOptions::default();
+        export_dump(ref_name, export_name, &options).expect("Error during export");
+        let root = fs::canonicalize(ref_name).expect("Unable to canonicalise").to_str().expect("to_str issue").to_string();
+        let content = fs::read_to_string(export_name).expect("Unable to read export");
+        let mut lines = content.lines();
+        assert_eq!(Some(options.header().as_str()), lines.next());
+        assert_eq!(Some(format!("{}{}", ROOT_PREFIX, root).as_str()), lines.next());
+        let names = lines.map(|l| filetree_info::FileTreeInfo::from(l).expect("Invalid line").name).collect::<Vec<String>>();
+        assert_eq!(vec!(".".to_string(), "a.txt".to_string(), "sub".to_string(), "sub/b.txt".to_string()), { let mut sorted = names.clone(); sorted.sort(); sorted });
+        assert_eq!(".", names[names.len() - 1]);
+        // Reference tree is no more available
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(import_dump(export_name, &ScanOptions { hash_names: true, ..ScanOptions::default() }).is_err());
+        assert_eq!(root, import_dump(export_name, &options).expect("Error during import"));
+        let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
+        assert_eq!(vec!((format!("{}/sub", root), "oth19/copy".to_string())),
+                   pair_names(compare_trees(&root, oth_name, &options, &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(&root)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(&root)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+        assert!(fs::remove_file(export_name).is_ok());
+    }
+    #[test]
+    fn test_rebase_dump() {
+        let old_name = "rebase20/old";
+        let new_name = "rebase20/new";
+        let oth_name = "oth20";
+        create_filetree(old_name, vec!(("sub/a.txt".to_string(), "Moved content".to_string()),
+                                       ("b.txt".to_string(), "Other content".to_string())));
+        create_filetree(oth_name, vec!(("copy/a.txt".to_string(), "Moved content".to_string())));
+        let options = ScanOptions::default();
+        generate_dump(old_name, &options).expect("Error during dump");
+        let content = fs::read_to_string(dump_name(old_name)).expect("Unable to read dump");
+        assert_eq!(Some(format!("{}{}", ROOT_PREFIX, old_name).as_str()), content.lines().nth(1));
+        assert!(content.lines().skip(2).all(|l| !l.contains(old_name)));
+        fs::rename(old_name, new_name).expect("Unable to move tree");
+        // Not in rebased dump as tree is not scanned again
+        fs::write("rebase20/new/extra.txt", "Not scanned").expect("Unable to write file");
+        assert!(rebase_dump(old_name, new_name).is_ok());
+        assert!(!fs::exists(dump_name(old_name)).unwrap());
+        let tree = load_dump(new_name).expect("Unable to load dump");
+        assert_eq!(new_name, tree.entries[tree.root()].name);
+        assert!(tree.entries.iter().all(|e| e.name.starts_with(new_name) && !e.name.ends_with("extra.txt")));
+        let mut output_mod: Box<dyn OutputModule> = Box::new(display_module::DisplayModule{});
+        assert_eq!(vec!(("rebase20/new/sub".to_string(), "oth20/copy".to_string())),
+                   pair_names(compare_trees(new_name, oth_name, &options, &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
+        assert!(rebase_dump(old_name, new_name).is_err());
+        assert!(fs::remove_dir_all("rebase20").is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(new_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(new_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    /// Keep track of reported entries in a shared list
+    struct RecordModule {
+        events: std::rc::Rc<std::cell::RefCell<Vec<String>>>
+    }
+    impl OutputModule for RecordModule {
+        fn treat_internal_doublon(&mut self, first: &filetree_info::FileTreeInfo, second: &filetree_info::FileTreeInfo) {
+            self.events.borrow_mut().push(format!("doublon {} {}", first.name, second.name));
+        }
+        fn treat_duplicated(&mut self, reference: &filetree_info::FileTreeInfo, other: &filetree_info::FileTreeInfo) -> Result<bool, String> {
+            self.events.borrow_mut().push(format!("duplicated {} {}", reference.name, other.name));
+            Ok(true)
+        }
+        fn treat_hardlinked(&mut self, first: &filetree_info::FileTreeInfo, second: &filetree_info::FileTreeInfo) {
+            self.events.borrow_mut().push(format!("hardlinked {} {}", first.name, second.name));
+        }
+    }
+    #[test]
+    fn test_hardlinks() {
+        let ref_name = "ref15";
+        let oth_name = "oth15";
+        create_filetree(ref_name, vec!(("a.txt".to_string(), "Shared content".to_string()),
+                                       ("b.txt".to_string(), "Reference only".to_string())));
+        create_filetree(oth_name, vec!(("x.txt".to_string(), "Shared content".to_string()),
+                                       ("z.txt".to_string(), "Shared content".to_string())));
+        fs::hard_link("oth15/x.txt", "oth15/y.txt").expect("Unable to create hardlink");
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut output_mod: Box<dyn OutputModule> = Box::new(RecordModule { events: events.clone() });
+        pair_names(compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison"));
+        // Entries with the same SHA1 are sorted by name in split files
+        assert_eq!(vec!("hardlinked oth15/x.txt oth15/y.txt".to_string(), "doublon oth15/y.txt oth15/z.txt".to_string()), *events.borrow());
+        events.borrow_mut().clear();
+        pair_names(index_compare_trees(ref_name, oth_name, &ScanOptions::default(), &SizeFilter::default(), &mut output_mod).expect("Error during comparison"));
+        assert_eq!(2, events.borrow().len());
+        assert!(events.borrow().iter().any(|e| e.starts_with("hardlinked ")));
+        let tree = load_dump(oth_name).expect("Unable to load dump");
+        let x = tree.entries.iter().find(|e| e.name == "oth15/x.txt").expect("x.txt not found");
+        let y = tree.entries.iter().find(|e| e.name == "oth15/y.txt").expect("y.txt not found");
+        assert!(x.same_inode(y));
+        assert_eq!(2, x.inode.expect("No inode").nlink);
+        assert!(hardlinked("oth15/x.txt", "oth15/y.txt"));
+        assert!(!hardlinked("oth15/x.txt", "oth15/z.txt"));
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    #[test]
+    fn test_followed_links_not_duplicated() {
+        let ref_name = "ref30";
+        let oth_name = "oth30";
+        create_filetree(ref_name, vec!(("a.txt".to_string(), "Reference only".to_string())));
+        create_filetree(oth_name, vec!(("dir/b.txt".to_string(), "Some content".to_string()),
+                                       ("dir/c.txt".to_string(), "Other content".to_string()),
+                                       ("x.txt".to_string(), "Last content".to_string())));
+        std::os::unix::fs::symlink("../oth30/dir", "ref30/to_other").expect("Unable to create link");
+        std::os::unix::fs::symlink("dir", "oth30/to_dir").expect("Unable to create link");
+        std::os::unix::fs::symlink("x.txt", "oth30/to_x").expect("Unable to create link");
+        let options = ScanOptions { follow_links: true, ..ScanOptions::default() };
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut output_mod: Box<dyn OutputModule> = Box::new(RecordModule { events: events.clone() });
+        assert!(compare_trees(ref_name, oth_name, &options, &SizeFilter::default(), &mut output_mod).expect("Error during comparison").is_empty());
+        assert!(events.borrow().is_empty());
+        assert!(index_compare_trees(ref_name, oth_name, &options, &SizeFilter::default(), &mut output_mod).expect("Error during comparison").is_empty());
+        assert!(events.borrow().is_empty());
+        // Real copy of a followed directory is still reported
+        create_filetree("oth30/copy", vec!(("b.txt".to_string(), "Some content".to_string()),
+                                           ("c.txt".to_string(), "Other content".to_string())));
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+        assert_eq!(vec!(("ref30/to_other".to_string(), "oth30/copy".to_string())),
+                   pair_names(compare_trees(ref_name, oth_name, &options, &SizeFilter::default(), &mut output_mod).expect("Error during comparison")));
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(oth_name)).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    #[test]
+    fn test_unknown_content_not_duplicated() {
+        let name = "unknown29.txt";
+        let sha1 = sha1::compute_sha1(Vec::from("same"));
+        let entries = [("unknown29/a", EntryKind::Unstable), ("unknown29/b", EntryKind::Unstable),
+                       ("unknown29/c", EntryKind::Error), ("unknown29/d", EntryKind::Error),
+                       ("unknown29/e", EntryKind::Incomplete), ("unknown29/f", EntryKind::Incomplete)];
+        let lines = entries.iter().map(|(name, kind)| format!("{}\n", filetree_info::FileTreeInfo { name: name.to_string(), kind: *kind, height: 0, nb_item: 0, size: 4, inode: None, sha1: sha1.clone() })).collect::<String>();
+        fs::write(name, lines).expect("Unable to write dump");
+        // Entries must exist to be reported
+        create_filetree("unknown29", entries.iter().map(|(name, _)| (name["unknown29/".len()..].to_string(), "same".to_string())).collect());
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut output_mod: Box<dyn OutputModule> = Box::new(RecordModule { events: events.clone() });
+        check_duplicated(name, &SizeFilter::default(), false, &mut output_mod).expect("Error during check");
+        assert!(events.borrow().is_empty());
+        assert!(fs::remove_file(name).is_ok());
+        assert!(fs::remove_dir_all("unknown29").is_ok());
+    }
+    #[test]
+    fn test_snapshot_trees() {
+        let old_name = "ref8";
+        let new_name = "oth8";
+        create_filetree(old_name, vec!(("photos/a.jpg".to_string(), "First photo".to_string()),
+                                       ("photos/b.jpg".to_string(), "Second photo".to_string()),
+                                       ("docs/readme.txt".to_string(), "Initial readme".to_string()),
+                                       ("docs/notes.txt".to_string(), "Some notes".to_string()),
+                                       ("docs/todo.txt".to_string(), "Things to do".to_string()),
+                                       ("obsolete/old.txt".to_string(), "Obsolete content".to_string()),
+                                      ));
+        create_filetree(new_name, vec!(("pictures/a.jpg".to_string(), "First photo".to_string()),
+                                       ("pictures/b.jpg".to_string(), "Second photo".to_string()),
+                                       ("docs/readme.txt".to_string(), "Updated readme".to_string()),
+                                       ("docs/notes.txt".to_string(), "Some notes".to_string()),
+                                       ("docs/done.txt".to_string(), "Things to do".to_string()),
+                                       ("new/file.txt".to_string(), "New content".to_string()),
+                                      ));
+        let result = snapshot_trees(old_name, new_name, &ScanOptions::default()).expect("Error during snapshot comparison");
+        let mut summary = result.iter().map(|e| (e.status, e.reference.clone(), e.other.clone())).collect::<Vec<_>>();
+        summary.sort_by(|a, b| (&a.1, &a.2).cmp(&(&b.1, &b.2)));
+        assert_eq!(vec!((diff_module::DiffStatus::Added, "".to_string(), "new".to_string()),
+                        (diff_module::DiffStatus::Unchanged, "docs/notes.txt".to_string(), "docs/notes.txt".to_string()),
+                        (diff_module::DiffStatus::Modified, "docs/readme.txt".to_string(), "docs/readme.txt".to_string()),
+                        (diff_module::DiffStatus::Moved, "docs/todo.txt".to_string(), "docs/done.txt".to_string()),
+                        (diff_module::DiffStatus::Deleted, "obsolete".to_string(), "".to_string()),
+                        (diff_module::DiffStatus::Moved, "photos".to_string(), "pictures".to_string()),
+                       ), summary);
+        assert!(fs::remove_dir_all(old_name).is_ok());
+        assert!(fs::remove_dir_all(new_name).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(old_name)).is_ok());
+        assert!(fs::remove_dir_all(dump_dir(new_name)).is_ok());
+        assert!(fs::remove_file(dump_name(old_name)).is_ok());
+        assert!(fs::remove_file(dump_name(new_name)).is_ok());
+    }
+    #[test]
+    fn test_near_duplicates() {
+        let ref_name = "ref10";
+        let oth_name = "oth10";
+        let photos = (0..5).map(|i| (format!("photos/{}.jpg", i), format!("Photo number {}", i))).collect::<Vec<(String, String)>>();
+        let mut ref_files = photos.clone();
+        ref_files.push(("photos/only_ref.jpg".to_string(), "Photo only in reference".to_string()));
+        let mut oth_files = photos.clone();
+        oth_files.push(("photos/extra.jpg".to_string(), "Photo only in other".to_string()));
+        oth_files.push(("small/0.jpg".to_string(), "Photo number 0".to_string()));
+        create_filetree(ref_name, ref_files);
+        create_filetree(oth_name, oth_files);
+        let options = ScanOptions::default();
+        generate_dump(ref_name, &options).expect("Error with reference");
+        generate_dump(oth_name, &options).expect("Error with other");
+        let reference = load_dump(ref_name).expect("Error when loading reference");
+        let other = load_dump(oth_name).expect("Error when loading other");
+        let near_options = NearOptions { min_items: 3, threshold: 0.6, metric: SimilarityMetric::Jaccard };
+        let result = near_module::find_near_duplicates(&reference, &other, &near_options);
+        assert_eq!(1, result.len());
+        assert_eq!("ref10", result[0].reference);
+        assert_eq!("oth10", result[0].other);
+        assert_eq!(vec!("ref10/photos/only_ref.jpg".to_string()), result[0].only_reference);
+        assert_eq!(vec!("oth10/photos/extra.jpg".to_string()), result[0].only_other);
+        assert_eq!(6 * 14, result[0].reclaimable);
+        assert!(fs::remove_dir_all(ref_name).is_ok());
+        assert!(fs::remove_dir_all(oth_name).is_ok());
+        assert!(fs::remove_file(dump_name(ref_name)).is_ok());
+        assert!(fs::remove_file(dump_name(oth_name)).is_ok());
+    }
+    #[test]
+    fn test_diff_trees() {
+        let ref_name = "ref7";
+        let oth_name = "oth7";
+        create_filetree(ref_name, vec!(("common/a.txt".to_string(), "This is a dummy file".to_string()),
+                                       ("common/b.txt".to_string(), "This is a an other dummy file".to_string()),
+                                       ("mixed/c.txt".to_string(), "This is yet an other dummy file".to_string()),
+                                       ("mixed/only_ref.txt".to_string(), "This file is only in reference".to_string()),
+                                       ("ref_dir/d.txt".to_string(), "Reference directory content".to_string()),
+                                      ));
+        create_filetree(oth_name, vec!(("moved/a.txt".to_string(), "This is a dummy file".to_string()),
+                                       ("moved/b.txt".to_string(), "This is a an other dummy file".to_string()),
+                                       ("c.txt".to_string(), "This is yet an other dummy file".to_string()),
+                                       ("oth_dir/e.txt".to_string(), "Other directory content".to_string()),
+                                      ));
+        let result = diff_trees(ref_name, oth_name, &ScanOptions::default()).expect("Error during diff");
+        let mut summary = result.iter().map(|e| (e.status, e.reference.clone(), e.other.clone())).collect::<Vec<_>>();
+        summary.sort_by(|a, b| (&a.1, &a.2).cmp(&(&b.1, &b.2)));
+        assert_eq!(vec!((diff_module::DiffStatus::OnlyInOther, "".to_string(), "oth7/oth_dir".to_string()),
+                        (diff_module::DiffStatus::InBoth, "ref7/common".to_string(), "oth7/moved".to_string()),
+                        (diff_module::DiffStatus::InBoth, "ref7/mixed/c.txt".to_string(), "oth7/c.txt".to_string()),
+                        (diff_module::DiffStatus::OnlyInReference, "ref7/mixed/only_ref.txt".to_string(), "".to_string()),
+                        (diff_module::DiffStatus::OnlyInReference, "ref7/ref_dir".to_string(), "".to_string()),
+                       ), summary);
         assert!(fs::remove_dir_all(ref_name).is_ok());
         assert!(fs::remove_dir_all(oth_name).is_ok());
         assert!(fs::remove_dir_all(dump_dir(ref_name)).is_ok());
@@ -0,0 +1,252 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ops::Range;
+
+use crate::dump_tree::DumpTree;
+use crate::filetree_info::EntryKind;
+use crate::sha1::Sha1Key;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SimilarityMetric {
+    /// Shared content divided by content of both directories
+    Jaccard,
+    /// Shared content divided by content of the smallest directory
+    Containment
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NearOptions {
+    /// Directories with less files are not considered
+    pub min_items: u32,
+    pub threshold: f64,
+    pub metric: SimilarityMetric
+}
+
+impl Default for NearOptions {
+    fn default() -> NearOptions {
+        NearOptions { min_items: 10, threshold: 0.9, metric: SimilarityMetric::Jaccard }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct NearDuplicate {
+    pub reference: String,
+    pub other: String,
+    pub similarity: f64,
+    pub only_reference: Vec<String>,
+    pub only_other: Vec<String>,
+    /// Size freed by removing other files whose content is also in reference directory
+    pub reclaimable: u64
+}
+
+/// Bytes freed by removing files. A file having several hardlinks only frees
+/// its size when all its hardlinks are removed
+fn reclaimable_size<'a>(tree: &DumpTree, files: impl Iterator<Item = &'a usize>) -> u64 {
+    let mut total = 0;
+    let mut inodes: HashMap<(u64, u64), (u64, u64, u64)> = HashMap::new();
+    for file in files {
+        let entry = &tree.entries[*file];
+        match &entry.inode {
+            Some(inode) => inodes.entry((inode.dev, inode.ino)).or_insert((inode.nlink, 0, entry.size)).1 += 1,
+            None => total += entry.size
+        }
+    }
+    total + inodes.values().filter(|(nlink, removed, _)| removed >= nlink).map(|(_, _, size)| size).sum::<u64>()
+}
+
+/// Files of a tree ordered so that files of a directory subtree are contiguous, with the
+/// range of files of directories having enough items. Content of a directory is only built
+/// when needed, to keep memory proportional to the number of files
+struct FileLayout {
+    files: Vec<usize>,
+    ranges: HashMap<usize, Range<usize>>
+}
+
+impl FileLayout {
+    fn new(tree: &DumpTree, min_items: u32) -> FileLayout {
+        let mut layout = FileLayout { files: Vec::new(), ranges: HashMap::new() };
+        if !tree.entries.is_empty() {
+            layout.collect(tree, tree.root(), min_items);
+        }
+        layout
+    }
+
+    fn collect(&mut self, tree: &DumpTree, dir: usize, min_items: u32) {
+        let start = self.files.len();
+        for child in tree.children(dir) {
+            let entry = &tree.entries[*child];
+            if entry.height == 0 {
+                // Only content of files and links is known, errors and special files would share a SHA1
+                if [EntryKind::File, EntryKind::Link].contains(&entry.kind) {
+                    self.files.push(*child);
+                }
+            } else {
+                self.collect(tree, *child, min_items);
+            }
+        }
+        if tree.entries[dir].nb_item >= min_items && start < self.files.len() {
+            self.ranges.insert(dir, start..self.files.len());
+        }
+    }
+
+    /// File SHA1 of a directory subtree, with the files having this SHA1
+    fn contents(&self, tree: &DumpTree, dir: usize) -> HashMap<Sha1Key, Vec<usize>> {
+        let mut result: HashMap<Sha1Key, Vec<usize>> = HashMap::new();
+        for file in self.files[self.ranges[&dir].clone()].iter() {
+            result.entry(tree.entries[*file].sha1.clone()).or_default().push(*file);
+        }
+        result
+    }
+}
+
+fn similarity(metric: SimilarityMetric, shared: usize, nb_reference: usize, nb_other: usize) -> f64 {
+    match metric {
+        SimilarityMetric::Jaccard => shared as f64 / (nb_reference + nb_other - shared) as f64,
+        SimilarityMetric::Containment => shared as f64 / nb_reference.min(nb_other) as f64
+    }
+}
+
+fn list_missing(tree: &DumpTree, contents: &HashMap<Sha1Key, Vec<usize>>, compared: &HashMap<Sha1Key, Vec<usize>>) -> Vec<String> {
+    let mut result = contents.iter()
+                             .filter(|(sha1, _)| !compared.contains_key(sha1))
+                             .flat_map(|(_, files)| files.iter().map(|f| tree.entries[*f].name.clone()))
+                             .collect::<Vec<String>>();
+    result.sort();
+    result
+}
+
+/// Directories of reference and other trees sharing most of their files without being
+/// equivalent. Pairs included in an already reported pair are not reported, result is
+/// sorted by decreasing reclaimable size
+pub fn find_near_duplicates(reference: &DumpTree, other: &DumpTree, options: &NearOptions) -> Vec<NearDuplicate> {
+    let ref_layout = FileLayout::new(reference, options.min_items);
+    let oth_layout = FileLayout::new(other, options.min_items);
+    let mut index: HashMap<&Sha1Key, Vec<usize>> = HashMap::new();
+    for file in oth_layout.files.iter() {
+        index.entry(&other.entries[*file].sha1).or_default().push(*file);
+    }
+    // Number of distinct SHA1 of other directories, computed once when first needed
+    let mut oth_sizes: HashMap<usize, usize> = HashMap::new();
+
+    // Treat highest directories first to be able to skip pairs of sub-directories
+    let mut ref_dirs = ref_layout.ranges.keys().copied().collect::<Vec<usize>>();
+    ref_dirs.sort_by(|a, b| reference.entries[*b].height.cmp(&reference.entries[*a].height).then(a.cmp(b)));
+    let mut reported = HashSet::new();
+    let mut result = Vec::new();
+    for ref_dir in ref_dirs {
+        let ref_files = ref_layout.contents(reference, ref_dir);
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for sha1 in ref_files.keys() {
+            // Directories containing this SHA1, ancestors of an already found directory are already found
+            let mut found = HashSet::new();
+            for file in index.get(sha1).into_iter().flatten() {
+                let mut ancestor = other.parent(*file);
+                while let Some(dir) = ancestor {
+                    if oth_layout.ranges.contains_key(&dir) && !found.insert(dir) {
+                        break;
+                    }
+                    ancestor = other.parent(dir);
+                }
+            }
+            for dir in found {
+                *shared.entry(dir).or_default() += 1;
+            }
+        }
+        let mut candidates = shared.into_iter().collect::<Vec<(usize, usize)>>();
+        candidates.sort_by(|a, b| other.entries[b.0].height.cmp(&other.entries[a.0].height).then(a.0.cmp(&b.0)));
+        for (oth_dir, nb_shared) in candidates {
+            let nb_other = *oth_sizes.entry(oth_dir).or_insert_with(|| oth_layout.contents(other, oth_dir).len());
+            let value = similarity(options.metric, nb_shared, ref_files.len(), nb_other);
+            if value < options.threshold || reference.entries[ref_dir].equivalent(&other.entries[oth_dir]) {
+                continue;
+            }
+            let ref_ancestors = reference.ancestors(ref_dir);
+            let oth_ancestors = other.ancestors(oth_dir);
+            if ref_ancestors.iter().any(|r| oth_ancestors.iter().any(|o| reported.contains(&(*r, *o)))) {
+                continue;
+            }
+            reported.insert((ref_dir, oth_dir));
+            let oth_files = oth_layout.contents(other, oth_dir);
+            let reclaimable = reclaimable_size(other, oth_files.iter()
+                                                              .filter(|(sha1, _)| ref_files.contains_key(sha1))
+                                                              .flat_map(|(_, files)| files.iter()));
+            result.push(NearDuplicate { reference: reference.entries[ref_dir].name.clone(),
+                                        other: other.entries[oth_dir].name.clone(),
+                                        similarity: value,
+                                        only_reference: list_missing(reference, &ref_files, &oth_files),
+                                        only_other: list_missing(other, &oth_files, &ref_files),
+                                        reclaimable });
+        }
+    }
+    result.sort_by_key(|item| std::cmp::Reverse(item.reclaimable));
+    result
+}
+
+pub fn display_near_duplicate(item: &NearDuplicate) {
+    eprintln!("~~~ {:.1}% {} <-> {} : {} bytes reclaimable", item.similarity * 100.0, item.reference, item.other, item.reclaimable);
+    item.only_reference.iter().for_each(|name| eprintln!("    <<< {}", name));
+    item.only_other.iter().for_each(|name| eprintln!("    >>> {}", name));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filetree_info::FileTreeInfo;
+    use crate::filetree_info::Inode;
+
+    #[test]
+    fn check_similarity() {
+        assert_eq!(0.5, similarity(SimilarityMetric::Jaccard, 2, 3, 3));
+        assert_eq!(1.0, similarity(SimilarityMetric::Containment, 2, 2, 4));
+        assert_eq!(0.5, similarity(SimilarityMetric::Jaccard, 2, 2, 4));
+    }
+
+    #[test]
+    fn check_file_layout() {
+        let tree = DumpTree::from_entries(vec!(FileTreeInfo::for_test("root/a", "a", 0, 0, 1),
+                                               FileTreeInfo::for_test("root/sub/b", "b", 0, 0, 1),
+                                               FileTreeInfo::for_test("root/sub/c", "b", 0, 0, 1),
+                                               FileTreeInfo::for_test("root/sub", "sub", 1, 2, 2),
+                                               FileTreeInfo::for_test("root/x/d", "d", 0, 0, 1),
+                                               FileTreeInfo { kind: EntryKind::Special, ..FileTreeInfo::for_test("root/x/fifo", "", 0, 0, 0) },
+                                               FileTreeInfo { kind: EntryKind::Error, ..FileTreeInfo::for_test("root/x/error", "", 0, 0, 0) },
+                                               FileTreeInfo::for_test("root/x", "x", 1, 3, 1),
+                                               FileTreeInfo::for_test("root", "root", 2, 8, 4)));
+        // Special and error entries are not content
+        let layout = FileLayout::new(&tree, 2);
+        assert_eq!(vec!(0, 1, 2, 4), layout.files);
+        assert_eq!(HashMap::from([(8, 0..4), (3, 1..3), (7, 3..4)]), layout.ranges);
+        let contents = layout.contents(&tree, 3);
+        assert_eq!(1, contents.len());
+        assert_eq!(Some(&vec!(1, 2)), contents.get(&tree.entries[1].sha1));
+        assert_eq!(3, layout.contents(&tree, 8).len());
+    }
+
+    #[test]
+    fn check_reclaimable_size() {
+        let file = |name: &str, inode: Option<Inode>| FileTreeInfo { inode, ..FileTreeInfo::for_test(name, "", 0, 0, 10) };
+        let shared = |nlink| Some(Inode { dev: 1, ino: 2, nlink });
+        let tree = DumpTree::from_entries(vec!(file("root/a", None), file("root/b", shared(2)), file("root/c", shared(2)), file("root/d", shared(3))));
+        assert_eq!(10, reclaimable_size(&tree, [0].iter()));
+        // Removing one of two hardlinks frees nothing
+        assert_eq!(0, reclaimable_size(&tree, [1].iter()));
+        assert_eq!(20, reclaimable_size(&tree, [0, 1, 2].iter()));
+    }
+}
@@ -15,7 +15,13 @@
       along with this program.  If not, see <http://www.gnu.org/licenses/>
 */
 
+use crate::filetree_info::FileTreeInfo;
+
+/// Modules writing a file finish it when dropped. Panicking while dropping would abort,
+/// so their write errors are only reported
 pub trait OutputModule {
-      fn treat_internal_doublon(& mut self, first: &str, second: &str);
-      fn treat_duplicated(&mut self, reference: &str, other: &str) -> Result<bool, String>;
+      fn treat_internal_doublon(& mut self, first: &FileTreeInfo, second: &FileTreeInfo);
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String>;
+      /// Identical entries which are already hardlinks to the same inode, removing one frees nothing
+      fn treat_hardlinked(&mut self, first: &FileTreeInfo, second: &FileTreeInfo);
 }
@@ -0,0 +1,404 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::output_module::OutputModule;
+use crate::ct_utils::{despecialise, escape_field, relative_name, unescape_field};
+use crate::filetree_info::FileTreeInfo;
+
+/// Quarantine directory when none is given
+pub const DEFAULT_DIRECTORY: &str = "quarantine";
+pub const MANIFEST_NAME: &str = "manifest.tsv";
+const MANIFEST_HEADER: &str = "date\toriginal\tquarantine\tsha1\treference";
+
+/// Entry moved to quarantine. Paths are absolute so that it can be restored from anywhere
+#[derive(Debug, PartialEq)]
+pub struct ManifestEntry {
+      /// Seconds since epoch when entry was moved
+      pub date: u64,
+      pub original: String,
+      pub quarantine: String,
+      pub sha1: String,
+      pub reference: String
+}
+
+impl ManifestEntry {
+      fn line(&self) -> String {
+            format!("{}\t{}\t{}\t{}\t{}\n", self.date, escape_field(&self.original), escape_field(&self.quarantine), self.sha1, escape_field(&self.reference))
+      }
+}
+
+/// Move duplicates of other directory in a session directory of quarantine directory,
+/// at the same path relatively to the session directory as relatively to other directory
+pub struct QuarantineModule {
+      session: PathBuf,
+      manifest: File,
+      other_root: String,
+      /// Reference tree is not available on this machine so its entries can not be checked
+      offline_reference: bool,
+      date: u64,
+      nb_moved: u64,
+      nb_skipped: u64,
+      size: u64
+}
+
+fn now() -> u64 {
+      SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn path_string(path: &Path) -> Result<String, String> {
+      path.to_str().map(String::from).ok_or(format!("to_str() issue with {}", path.display()))
+}
+
+fn canonical(path: &Path) -> Result<PathBuf, String> {
+      fs::canonicalize(path).map_err(|e| format!("Unable to get absolute path of {} : {}", path.display(), e))
+}
+
+/// Absolute path of an entry. Its parent directories are resolved but not the entry itself
+/// which may be a symbolic link or may not exist anymore
+fn absolute_name(path: &Path) -> Result<PathBuf, String> {
+      let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (if parent.as_os_str().is_empty() { Path::new(".") } else { parent }, name),
+            _ => return canonical(path)
+      };
+      let parent = match fs::canonicalize(parent) {
+            Ok(parent) => parent,
+            Err(_) => absolute_name(parent)?
+      };
+      Ok(parent.join(name))
+}
+
+/// Remove directories left empty between a removed entry and quarantine directory
+fn remove_empty_parents(path: &Path, directory: &Path) {
+      let mut current = path.parent();
+      while let Some(parent) = current {
+            if parent == directory || !parent.starts_with(directory) || fs::remove_dir(parent).is_err() {
+                  break;
+            }
+            current = parent.parent();
+      }
+}
+
+pub fn read_manifest(directory: &Path) -> Result<Vec<ManifestEntry>, String> {
+      let filename = directory.join(MANIFEST_NAME);
+      let content = match fs::read_to_string(&filename) {
+            Ok(content) => content,
+            Err(e) => return Err(format!("Unable to read file {} : {}", filename.display(), e))
+      };
+      let mut lines = content.lines();
+      if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(format!("{} is not a quarantine manifest", filename.display()));
+      }
+      let mut entries = Vec::new();
+      for (number, line) in lines.enumerate() {
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            let date = match (fields.len(), fields[0].parse::<u64>()) {
+                  (5, Ok(date)) => date,
+                  _ => return Err(format!("Invalid line {} in {}", number + 2, filename.display()))
+            };
+            entries.push(ManifestEntry { date, original: unescape_field(fields[1]), quarantine: unescape_field(fields[2]), sha1: fields[3].to_string(), reference: unescape_field(fields[4]) });
+      }
+      Ok(entries)
+}
+
+/// Manifest is written next to the existing one then renamed over it to never lose entries
+fn write_manifest(directory: &Path, entries: &[ManifestEntry]) -> Result<(), String> {
+      let filename = directory.join(MANIFEST_NAME);
+      let temporary = directory.join(format!(".{}.{}", MANIFEST_NAME, std::process::id()));
+      let content = entries.iter().fold(format!("{}\n", MANIFEST_HEADER), |content, entry| content + &entry.line());
+      if let Err(e) = fs::write(&temporary, content) {
+            return Err(format!("Unable to write file {} : {}", temporary.display(), e));
+      }
+      fs::rename(&temporary, &filename).map_err(|e| format!("Unable to rename {} : {}", temporary.display(), e))
+}
+
+impl QuarantineModule {
+      pub fn new(directory: &str, other_root: &str, offline_reference: bool) -> Result<QuarantineModule, String> {
+            if absolute_name(Path::new(directory))?.starts_with(canonical(Path::new(other_root))?) {
+                  return Err(format!("Quarantine directory {} can not be located in {}", directory, other_root));
+            }
+            if let Err(e) = fs::create_dir_all(directory) {
+                  return Err(format!("Unable to create directory {} : {}", directory, e));
+            }
+            let directory = canonical(Path::new(directory))?;
+            // Several sessions may start during the same second
+            let date = now();
+            let mut index = 0;
+            let session = loop {
+                  let session = directory.join(if index == 0 { date.to_string() } else { format!("{}.{}", date, index) });
+                  match fs::create_dir(&session) {
+                        Ok(()) => break session,
+                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => index += 1,
+                        Err(e) => return Err(format!("Unable to create directory {} : {}", session.display(), e))
+                  }
+            };
+            let filename = directory.join(MANIFEST_NAME);
+            let new_manifest = !filename.exists();
+            let mut manifest = match OpenOptions::new().create(true).append(true).open(&filename) {
+                  Ok(file) => file,
+                  Err(e) => return Err(format!("Unable to open file {} : {}", filename.display(), e))
+            };
+            if new_manifest && let Err(e) = manifest.write_all(format!("{}\n", MANIFEST_HEADER).as_bytes()) {
+                  return Err(format!("Unable to write in file {} : {}", filename.display(), e));
+            }
+            Ok(QuarantineModule { session, manifest, other_root: other_root.to_string(), offline_reference, date, nb_moved: 0, nb_skipped: 0, size: 0 })
+      }
+
+      fn quarantine(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<PathBuf, String> {
+            let reference_name = if self.offline_reference {
+                  reference.name.clone()
+            } else if fs::symlink_metadata(&reference.name).is_ok() {
+                  path_string(&absolute_name(Path::new(&reference.name))?)?
+            } else {
+                  return Err(String::from("reference entry does not exist anymore"));
+            };
+            let original = absolute_name(Path::new(&other.name))?;
+            if fs::symlink_metadata(&original).is_err() {
+                  return Err(String::from("entry does not exist anymore"));
+            }
+            let destination = self.session.join(relative_name(&self.other_root, &other.name));
+            if let Some(parent) = destination.parent() && let Err(e) = fs::create_dir_all(parent) {
+                  return Err(format!("unable to create directory {} : {}", parent.display(), e));
+            }
+            let entry = ManifestEntry { date: self.date, original: path_string(&original)?, quarantine: path_string(&destination)?, sha1: other.sha1.to_string(), reference: reference_name };
+            // An entry missing from manifest could not be restored so it is recorded before being moved
+            if let Err(e) = self.manifest.write_all(entry.line().as_bytes()).and_then(|_| self.manifest.sync_data()) {
+                  return Err(format!("unable to write manifest : {}", e));
+            }
+            match fs::rename(&original, &destination) {
+                  Ok(()) => Ok(destination),
+                  Err(e) if e.kind() == io::ErrorKind::CrossesDevices => Err(String::from("quarantine directory is on another filesystem")),
+                  Err(e) => Err(format!("unable to move it to {} : {}", destination.display(), e))
+            }
+      }
+}
+
+impl OutputModule for QuarantineModule {
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            eprintln!("!!! Doublon {} <-> {}", despecialise(&first.name), despecialise(&second.name));
+      }
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            match self.quarantine(reference, other) {
+                  Ok(destination) => {
+                        eprintln!("{} QUARANTINED IN {}", despecialise(&other.name), despecialise(&destination.to_string_lossy()));
+                        self.nb_moved += 1;
+                        self.size += other.size;
+                  },
+                  Err(reason) => {
+                        eprintln!("!!! {} not quarantined : {}", despecialise(&other.name), reason);
+                        self.nb_skipped += 1;
+                  }
+            }
+            Ok(true)
+      }
+      fn treat_hardlinked(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            eprintln!("=== Hardlinked {} <-> {}", despecialise(&first.name), despecialise(&second.name));
+      }
+}
+
+impl Drop for QuarantineModule {
+    fn drop(&mut self) {
+        if self.nb_moved == 0 {
+            let _ = fs::remove_dir(&self.session);
+        }
+        println!("==> {} entries moved in {}, {} skipped, {} bytes", self.nb_moved, self.session.display(), self.nb_skipped, self.size);
+    }
+}
+
+/// Manifest may have been edited, only entries located in quarantine directory are moved or removed
+fn check_location(entry: &ManifestEntry, directory: &Path) -> Result<(), String> {
+      let quarantine = Path::new(&entry.quarantine);
+      if quarantine.components().any(|c| c == Component::ParentDir) || quarantine == directory || !quarantine.starts_with(directory) {
+            return Err(format!("{} is not located in {}", entry.quarantine, directory.display()));
+      }
+      Ok(())
+}
+
+fn restore_entry(entry: &ManifestEntry, directory: &Path) -> Result<(), String> {
+      check_location(entry, directory)?;
+      let original = Path::new(&entry.original);
+      if fs::symlink_metadata(original).is_ok() {
+            return Err(String::from("original path exists"));
+      }
+      if let Some(parent) = original.parent() && let Err(e) = fs::create_dir_all(parent) {
+            return Err(format!("unable to create directory {} : {}", parent.display(), e));
+      }
+      fs::rename(&entry.quarantine, original).map_err(|e| format!("unable to move {} : {}", entry.quarantine, e))
+}
+
+/// Move back quarantined entries located in one of selected paths, or all entries if none is selected.
+/// Entries whose original path exists again are kept in quarantine
+pub fn restore(directory: &str, selection: &[String]) -> Result<(), String> {
+      let directory = canonical(Path::new(directory))?;
+      let entries = read_manifest(&directory)?;
+      let selection = selection.iter().map(|s| absolute_name(Path::new(s))).collect::<Result<Vec<PathBuf>, String>>()?;
+      for selected in selection.iter().filter(|s| !entries.iter().any(|e| Path::new(&e.original).starts_with(s))) {
+            eprintln!("!!! No quarantined entry in {}", despecialise(&selected.to_string_lossy()));
+      }
+      let mut kept = Vec::new();
+      let (mut nb_restored, mut nb_failed) = (0, 0);
+      for entry in entries {
+            if !selection.is_empty() && !selection.iter().any(|s| Path::new(&entry.original).starts_with(s)) {
+                  kept.push(entry);
+                  continue;
+            }
+            // Entry is recorded before being moved, the move may have failed
+            if fs::symlink_metadata(&entry.quarantine).is_err() {
+                  eprintln!("!!! {} not in quarantine, removed from manifest", despecialise(&entry.original));
+                  continue;
+            }
+            match restore_entry(&entry, &directory) {
+                  Ok(()) => {
+                        eprintln!("{} RESTORED", despecialise(&entry.original));
+                        remove_empty_parents(Path::new(&entry.quarantine), &directory);
+                        nb_restored += 1;
+                  },
+                  Err(reason) => {
+                        eprintln!("!!! {} not restored : {}", despecialise(&entry.original), reason);
+                        nb_failed += 1;
+                        kept.push(entry);
+                  }
+            }
+      }
+      write_manifest(&directory, &kept)?;
+      println!("==> {} entries restored, {} still in quarantine", nb_restored, kept.len());
+      if nb_failed > 0 {
+            return Err(format!("{} entries could not be restored", nb_failed));
+      }
+      Ok(())
+}
+
+/// Definitively remove entries quarantined for more than the given number of days
+pub fn purge(directory: &str, days: u64) -> Result<(), String> {
+      let directory = canonical(Path::new(directory))?;
+      let limit = now().saturating_sub(days.saturating_mul(24 * 3600));
+      let mut kept = Vec::new();
+      let (mut nb_purged, mut nb_failed) = (0, 0);
+      for entry in read_manifest(&directory)? {
+            if entry.date > limit {
+                  kept.push(entry);
+                  continue;
+            }
+            if let Err(reason) = check_location(&entry, &directory) {
+                  eprintln!("!!! {} not purged : {}", despecialise(&entry.quarantine), reason);
+                  nb_failed += 1;
+                  kept.push(entry);
+                  continue;
+            }
+            let quarantine = Path::new(&entry.quarantine);
+            let result = match fs::symlink_metadata(quarantine) {
+                  Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(quarantine),
+                  Ok(_) => fs::remove_file(quarantine),
+                  // Already removed by hand
+                  Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                  Err(e) => Err(e)
+            };
+            match result {
+                  Ok(()) => {
+                        remove_empty_parents(quarantine, &directory);
+                        nb_purged += 1;
+                  },
+                  Err(e) => {
+                        eprintln!("!!! {} not purged : {}", despecialise(&entry.quarantine), e);
+                        nb_failed += 1;
+                        kept.push(entry);
+                  }
+            }
+      }
+      write_manifest(&directory, &kept)?;
+      println!("==> {} entries purged, {} still in quarantine", nb_purged, kept.len());
+      if nb_failed > 0 {
+            return Err(format!("{} entries could not be purged", nb_failed));
+      }
+      Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+
+    #[test]
+    fn check_quarantine() {
+        let root = Path::new("quarantine24");
+        fs::create_dir_all(root.join("ref/sub")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth/sub")).expect("Unable to create directory");
+        for name in ["ref/a", "oth/a", "ref/sub/b", "oth/sub/b", "oth/sub/c"] {
+            fs::write(root.join(name), "Same").expect("Unable to write file");
+        }
+        assert!(QuarantineModule::new("quarantine24/oth/q", "quarantine24/oth", false).is_err());
+        assert!(!root.join("oth/q").exists());
+        {
+            let mut module = QuarantineModule::new("quarantine24/q", "quarantine24/oth", false).expect("Unable to create module");
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("quarantine24/ref/a", "", 0, 0, 4), &FileTreeInfo::for_test("quarantine24/oth/a", "", 0, 0, 4)).unwrap());
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("quarantine24/ref/sub", "", 0, 0, 4), &FileTreeInfo::for_test("quarantine24/oth/sub", "", 0, 0, 4)).unwrap());
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("quarantine24/ref/missing", "", 0, 0, 4), &FileTreeInfo::for_test("quarantine24/oth/d", "", 0, 0, 4)).unwrap());
+            assert_eq!((2, 1), (module.nb_moved, module.nb_skipped));
+        }
+        assert!(!root.join("oth/a").exists());
+        assert!(!root.join("oth/sub").exists());
+        let directory = canonical(&root.join("q")).expect("No quarantine directory");
+        let entries = read_manifest(&directory).expect("Unable to read manifest");
+        assert_eq!(2, entries.len());
+        let absolute = canonical(root).expect("No test directory");
+        assert_eq!(absolute.join("oth/sub").to_str().unwrap(), entries[1].original);
+        assert_eq!(absolute.join("ref/sub").to_str().unwrap(), entries[1].reference);
+        assert_eq!(directory.join(format!("{}/sub", entries[1].date)).to_str().unwrap(), entries[1].quarantine);
+        assert_eq!("Same", fs::read_to_string(Path::new(&entries[1].quarantine).join("c")).expect("Unable to read file"));
+
+        // Only selected entries are restored, nothing is overwritten
+        assert_eq!(Ok(()), restore("quarantine24/q", &["quarantine24/oth/sub".to_string()]));
+        assert_eq!("Same", fs::read_to_string(root.join("oth/sub/c")).expect("Unable to read file"));
+        fs::write(root.join("oth/a"), "New").expect("Unable to write file");
+        assert!(restore("quarantine24/q", &[]).is_err());
+        assert_eq!("New", fs::read_to_string(root.join("oth/a")).expect("Unable to read file"));
+        assert_eq!(1, read_manifest(&directory).expect("Unable to read manifest").len());
+
+        assert_eq!(Ok(()), purge("quarantine24/q", 1));
+        assert_eq!(1, read_manifest(&directory).expect("Unable to read manifest").len());
+        assert_eq!(Ok(()), purge("quarantine24/q", 0));
+        assert!(read_manifest(&directory).expect("Unable to read manifest").is_empty());
+        assert_eq!(1, fs::read_dir(&directory).expect("Unable to read directory").count());
+
+        // Entry recorded but not moved is removed from manifest without touching original path
+        let never_moved = ManifestEntry { date: 0, original: absolute.join("oth/a").to_str().unwrap().to_string(), quarantine: directory.join("0/a").to_str().unwrap().to_string(),
+                                          sha1: String::new(), reference: absolute.join("ref/a").to_str().unwrap().to_string() };
+        write_manifest(&directory, &[never_moved]).expect("Unable to write manifest");
+        assert_eq!(Ok(()), restore("quarantine24/q", &[]));
+        assert!(read_manifest(&directory).expect("Unable to read manifest").is_empty());
+        assert_eq!("New", fs::read_to_string(root.join("oth/a")).expect("Unable to read file"));
+
+        // Entries of an edited manifest located out of quarantine directory are neither removed nor moved
+        let outside = [absolute.join("ref/a"), directory.join("../ref/a"), directory.clone()].map(|quarantine|
+            ManifestEntry { date: 0, original: absolute.join("oth/moved").to_str().unwrap().to_string(), quarantine: quarantine.to_str().unwrap().to_string(), sha1: String::new(), reference: String::new() });
+        write_manifest(&directory, &outside).expect("Unable to write manifest");
+        assert!(purge("quarantine24/q", 0).is_err());
+        assert!(restore("quarantine24/q", &[]).is_err());
+        assert_eq!(3, read_manifest(&directory).expect("Unable to read manifest").len());
+        assert_eq!("Same", fs::read_to_string(root.join("ref/a")).expect("Unable to read file"));
+        assert!(directory.exists());
+        assert!(!root.join("oth/moved").exists());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+}
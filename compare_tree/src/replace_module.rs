@@ -0,0 +1,563 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+#[cfg(target_os = "linux")]
+use std::ffi::{c_char, c_int, c_uint, c_ulong};
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+
+use crate::output_module::OutputModule;
+use crate::ct_utils::despecialise;
+use crate::filetree_info::FileTreeInfo;
+
+#[cfg(target_os = "linux")]
+unsafe extern "C" {
+      fn renameat2(olddirfd: c_int, oldpath: *const c_char, newdirfd: c_int, newpath: *const c_char, flags: c_uint) -> c_int;
+      fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+#[cfg(target_os = "linux")]
+const AT_FDCWD: c_int = -100;
+#[cfg(target_os = "linux")]
+const RENAME_EXCHANGE: c_uint = 2;
+/// _IOWR(0x94, 54, struct file_dedupe_range)
+#[cfg(target_os = "linux")]
+const FIDEDUPERANGE: c_ulong = 0xC018_9436;
+const FILE_DEDUPE_RANGE_DIFFERS: i32 = 1;
+/// Some filesystems limit the length deduplicated by a single call
+const DEDUPE_CHUNK: u64 = 16 * 1024 * 1024;
+
+/// struct file_dedupe_range with a single destination
+#[repr(C)]
+struct DedupeRange {
+      src_offset: u64,
+      src_length: u64,
+      dest_count: u16,
+      reserved1: u16,
+      reserved2: u32,
+      dest_fd: i64,
+      dest_offset: u64,
+      bytes_deduped: u64,
+      status: i32,
+      reserved: u32
+}
+
+/// Error of deduplication, filesystem not supporting it allows to use another method
+#[derive(Debug, PartialEq)]
+pub enum DedupeError {
+      Unsupported(String),
+      Failed(String)
+}
+
+/// How duplicates are made to point to reference entries
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ReplaceMethod {
+      #[default]
+      Hardlink,
+      /// Symbolic link whose target is the absolute path of reference entry
+      AbsoluteSymlink,
+      /// Symbolic link whose target is the path of reference entry from the directory of the link
+      RelativeSymlink,
+      /// Files stay independent but share their extents on copy-on-write filesystems
+      Reflink
+}
+
+impl ReplaceMethod {
+      fn label(&self) -> &'static str {
+            match self {
+                  ReplaceMethod::Hardlink => "hardlinks",
+                  ReplaceMethod::AbsoluteSymlink | ReplaceMethod::RelativeSymlink => "symbolic links",
+                  ReplaceMethod::Reflink => "reflinks"
+            }
+      }
+}
+
+/// Duplicates are kept at their path but share the data of reference entries
+pub struct ReplaceModule {
+      method: ReplaceMethod,
+      /// Check that symbolic link gives access to the same content as replaced file.
+      /// Directories are always checked since their removal can not be undone
+      verify: bool,
+      /// Method used when filesystem does not support reflinks
+      fallback: Option<ReplaceMethod>,
+      nb_replaced: u32,
+      nb_skipped: u32,
+      freed: u64
+}
+
+/// Compare contents byte by byte, SHA1 of dump may be outdated
+fn same_content(first: &Path, second: &Path) -> Result<bool, String> {
+      let open = |path: &Path| File::open(path).map_err(|e| format!("Unable to open {} : {}", path.display(), e));
+      let (mut first_file, mut second_file) = (open(first)?, open(second)?);
+      let mut first_buffer = vec!(0; 65536);
+      let mut second_buffer = vec!(0; 65536);
+      loop {
+            let nb_read = first_file.read(&mut first_buffer).map_err(|e| format!("Unable to read {} : {}", first.display(), e))?;
+            let mut nb_second = 0;
+            while nb_second < nb_read {
+                  match second_file.read(&mut second_buffer[nb_second..nb_read]) {
+                        Ok(0) => return Ok(false),
+                        Ok(n) => nb_second += n,
+                        Err(e) => return Err(format!("Unable to read {} : {}", second.display(), e))
+                  }
+            }
+            if first_buffer[..nb_read] != second_buffer[..nb_read] {
+                  return Ok(false);
+            }
+            if nb_read == 0 {
+                  // Second file must end too
+                  return match second_file.read(&mut second_buffer) {
+                        Ok(n) => Ok(n == 0),
+                        Err(e) => Err(format!("Unable to read {} : {}", second.display(), e))
+                  };
+            }
+      }
+}
+
+/// Compare trees on disk, symbolic links are compared by target
+fn same_tree(first: &Path, second: &Path) -> Result<bool, String> {
+      let metadata = |path: &Path| fs::symlink_metadata(path).map_err(|e| format!("Unable to collect metadata from {} : {}", path.display(), e));
+      let (first_metadata, second_metadata) = (metadata(first)?, metadata(second)?);
+      if first_metadata.is_symlink() || second_metadata.is_symlink() {
+            return Ok(first_metadata.is_symlink() && second_metadata.is_symlink() && fs::read_link(first).ok() == fs::read_link(second).ok());
+      }
+      if first_metadata.is_file() && second_metadata.is_file() {
+            return same_content(first, second);
+      }
+      if !first_metadata.is_dir() || !second_metadata.is_dir() {
+            return Ok(false);
+      }
+      let names = |path: &Path| -> Result<Vec<std::ffi::OsString>, String> {
+            let entries = fs::read_dir(path).map_err(|e| format!("Unable to read directory {} : {}", path.display(), e))?;
+            let mut names = entries.map(|e| e.map(|e| e.file_name())).collect::<Result<Vec<_>, _>>().map_err(|e| format!("Unable to read directory {} : {}", path.display(), e))?;
+            names.sort();
+            Ok(names)
+      };
+      let first_names = names(first)?;
+      if first_names != names(second)? {
+            return Ok(false);
+      }
+      for name in first_names.iter() {
+            if !same_tree(&first.join(name), &second.join(name))? {
+                  return Ok(false);
+            }
+      }
+      Ok(true)
+}
+
+/// Path leading from a directory to a target, both being canonical
+fn relative_target(directory: &Path, target: &Path) -> PathBuf {
+      let directory = directory.components().collect::<Vec<Component>>();
+      let target = target.components().collect::<Vec<Component>>();
+      let common = directory.iter().zip(target.iter()).take_while(|(a, b)| a == b).count();
+      let mut result = PathBuf::new();
+      (common..directory.len()).for_each(|_| result.push(".."));
+      target[common..].iter().for_each(|c| result.push(c));
+      if result.as_os_str().is_empty() {
+            result.push(".");
+      }
+      result
+}
+
+/// Atomically swap two paths of the same filesystem, they may be of different types
+#[cfg(target_os = "linux")]
+fn exchange(first: &Path, second: &Path) -> Result<(), String> {
+      use std::ffi::CString;
+      use std::os::unix::ffi::OsStrExt;
+      let c_path = |path: &Path| CString::new(path.as_os_str().as_bytes()).map_err(|_| format!("Null byte in {}", path.display()));
+      let (first_path, second_path) = (c_path(first)?, c_path(second)?);
+      // SAFETY: both paths are nul terminated strings which outlive the call
+      if unsafe { renameat2(AT_FDCWD, first_path.as_ptr(), AT_FDCWD, second_path.as_ptr(), RENAME_EXCHANGE) } != 0 {
+            return Err(format!("unable to exchange {} and {} : {}", first.display(), second.display(), io::Error::last_os_error()));
+      }
+      Ok(())
+}
+
+/// renameat2 is specific to Linux
+#[cfg(not(target_os = "linux"))]
+fn exchange(first: &Path, second: &Path) -> Result<(), String> {
+      Err(format!("unable to exchange {} and {} : {}", first.display(), second.display(), io::Error::from(io::ErrorKind::Unsupported)))
+}
+
+/// Path next to an entry, used to prepare its replacement
+fn temporary_path(path: &Path) -> Result<PathBuf, String> {
+      let name = path.file_name().and_then(|n| n.to_str()).ok_or(format!("No file name in {}", path.display()))?;
+      Ok(path.with_file_name(format!(".{}.compare_tree.{}", name, std::process::id())))
+}
+
+/// Replace other file or directory by a symbolic link to reference entry. Link is created
+/// next to other entry then exchanged with it so other path always exists.
+/// Content reached through the link is always compared to a directory before it is removed
+pub fn replace_with_symlink(reference: &Path, other: &Path, relative: bool, verify: bool) -> Result<(), String> {
+      let other_metadata = fs::symlink_metadata(other).map_err(|e| format!("Unable to collect metadata from {} : {}", other.display(), e))?;
+      if other_metadata.is_symlink() {
+            return Err(String::from("already a symbolic link"));
+      }
+      let canonical = |path: &Path| fs::canonicalize(path).map_err(|e| format!("Unable to canonicalise {} : {}", path.display(), e));
+      let reference = canonical(reference)?;
+      let target = if relative {
+            let directory = other.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+            relative_target(&canonical(directory)?, &reference)
+      } else {
+            reference
+      };
+      let temporary = temporary_path(other)?;
+      if let Err(e) = std::os::unix::fs::symlink(&target, &temporary) {
+            return Err(format!("unable to create link {} : {}", temporary.display(), e));
+      }
+      let verified = if verify || other_metadata.is_dir() {
+            fs::canonicalize(&temporary).map_err(|e| format!("link to {} does not resolve : {}", target.display(), e)).and_then(|resolved| same_tree(&resolved, other))
+      } else {
+            Ok(true)
+      };
+      let result = match verified {
+            Ok(false) => Err(format!("link to {} does not give the same content", target.display())),
+            Err(e) => Err(e),
+            Ok(true) if other_metadata.is_dir() => exchange(&temporary, other),
+            Ok(true) => fs::rename(&temporary, other).map_err(|e| format!("unable to rename {} : {}", temporary.display(), e))
+      };
+      if result.is_err() {
+            let _ = fs::remove_file(&temporary);
+            return result;
+      }
+      // Replaced directory is now at temporary path
+      if other_metadata.is_dir() && let Err(e) = fs::remove_dir_all(&temporary) {
+            return Err(format!("unable to remove replaced directory {} : {}", temporary.display(), e));
+      }
+      Ok(())
+}
+
+/// Filesystem or kernel not supporting deduplication of these files
+fn unsupported(error: &io::Error) -> bool {
+      matches!(error.kind(), io::ErrorKind::CrossesDevices | io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported)
+}
+
+#[cfg(target_os = "linux")]
+fn dedupe_range(source: &File, range: &mut DedupeRange) -> io::Result<()> {
+      // SAFETY: range is a file_dedupe_range followed by its single file_dedupe_range_info, it outlives the call
+      if unsafe { ioctl(source.as_raw_fd(), FIDEDUPERANGE, range as *mut DedupeRange) } != 0 {
+            return Err(io::Error::last_os_error());
+      }
+      Ok(())
+}
+
+/// FIDEDUPERANGE is specific to Linux
+#[cfg(not(target_os = "linux"))]
+fn dedupe_range(_source: &File, _range: &mut DedupeRange) -> io::Result<()> {
+      Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Make other file share the extents of reference file. The kernel checks that contents are
+/// identical while holding both files, unlike FICLONE which would overwrite other file blindly.
+/// Return the size deduplicated
+pub fn dedupe(reference: &Path, other: &Path) -> Result<u64, DedupeError> {
+      let failed = |message: String| DedupeError::Failed(message);
+      let metadata = |path: &Path| fs::symlink_metadata(path).map_err(|e| failed(format!("Unable to collect metadata from {} : {}", path.display(), e)));
+      let (ref_metadata, other_metadata) = (metadata(reference)?, metadata(other)?);
+      if !ref_metadata.is_file() || !other_metadata.is_file() {
+            return Err(failed(String::from("not regular files")));
+      }
+      if ref_metadata.ino() == other_metadata.ino() && ref_metadata.dev() == other_metadata.dev() {
+            return Err(failed(String::from("already hardlinked")));
+      }
+      if ref_metadata.len() != other_metadata.len() {
+            return Err(failed(String::from("contents differ")));
+      }
+      if ref_metadata.dev() != other_metadata.dev() {
+            return Err(DedupeError::Unsupported(String::from("files on different filesystems")));
+      }
+      let source = File::open(reference).map_err(|e| failed(format!("Unable to open {} : {}", reference.display(), e)))?;
+      // Writable destination is only required when not owning the file
+      let destination = match OpenOptions::new().read(true).write(true).open(other) {
+            Ok(file) => file,
+            Err(_) => File::open(other).map_err(|e| failed(format!("Unable to open {} : {}", other.display(), e)))?
+      };
+      let length = ref_metadata.len();
+      let mut offset = 0;
+      while offset < length {
+            let mut range = DedupeRange { src_offset: offset, src_length: (length - offset).min(DEDUPE_CHUNK), dest_count: 1, reserved1: 0, reserved2: 0,
+                                          dest_fd: destination.as_raw_fd() as i64, dest_offset: offset, bytes_deduped: 0, status: 0, reserved: 0 };
+            if let Err(error) = dedupe_range(&source, &mut range) {
+                  return Err(if unsupported(&error) { DedupeError::Unsupported(error.to_string()) } else { failed(format!("deduplication failed : {}", error)) });
+            }
+            if range.status == FILE_DEDUPE_RANGE_DIFFERS {
+                  return Err(failed(String::from("contents differ")));
+            }
+            if range.status < 0 {
+                  let error = io::Error::from_raw_os_error(-range.status);
+                  return Err(if unsupported(&error) { DedupeError::Unsupported(error.to_string()) } else { failed(format!("deduplication failed : {}", error)) });
+            }
+            if range.bytes_deduped == 0 {
+                  return Err(failed(String::from("deduplication does not progress")));
+            }
+            offset += range.bytes_deduped;
+      }
+      Ok(length)
+}
+
+/// Replace other file by a hardlink to reference file. Link is created next to other
+/// file then renamed over it so other path always exists. Return the size freed
+pub fn replace_with_hardlink(reference: &Path, other: &Path) -> Result<u64, String> {
+      let metadata = |path: &Path| fs::symlink_metadata(path).map_err(|e| format!("Unable to collect metadata from {} : {}", path.display(), e));
+      let (ref_metadata, other_metadata) = (metadata(reference)?, metadata(other)?);
+      if !ref_metadata.is_file() || !other_metadata.is_file() {
+            return Err(String::from("not regular files"));
+      }
+      if ref_metadata.dev() != other_metadata.dev() {
+            return Err(String::from("on different filesystems"));
+      }
+      if ref_metadata.ino() == other_metadata.ino() {
+            return Err(String::from("already hardlinked"));
+      }
+      // Both paths share the inode once linked so access rights can not be kept if they differ
+      if (ref_metadata.mode(), ref_metadata.uid(), ref_metadata.gid()) != (other_metadata.mode(), other_metadata.uid(), other_metadata.gid()) {
+            return Err(String::from("permissions or ownership differ"));
+      }
+      if !same_content(reference, other)? {
+            return Err(String::from("contents differ"));
+      }
+      let temporary = temporary_path(other)?;
+      if let Err(e) = fs::hard_link(reference, &temporary) {
+            return Err(format!("unable to create link {} : {}", temporary.display(), e));
+      }
+      if let Err(e) = fs::rename(&temporary, other) {
+            let _ = fs::remove_file(&temporary);
+            return Err(format!("unable to rename {} : {}", temporary.display(), e));
+      }
+      // Data is only freed when duplicate had no other hardlink
+      Ok(if other_metadata.nlink() == 1 { other_metadata.len() } else { 0 })
+}
+
+/// Files of other directory with the reference file at the same relative path.
+/// Symbolic links are not followed
+fn file_pairs(reference: &Path, other: &Path, pairs: &mut Vec<(PathBuf, PathBuf)>) -> Result<(), String> {
+      let entries = fs::read_dir(other).map_err(|e| format!("Unable to read directory {} : {}", other.display(), e))?;
+      for entry in entries {
+            let entry = entry.map_err(|e| format!("Unable to read directory {} : {}", other.display(), e))?;
+            let file_type = entry.file_type().map_err(|e| format!("Unable to get type of {} : {}", entry.path().display(), e))?;
+            let reference_path = reference.join(entry.file_name());
+            if file_type.is_dir() {
+                  file_pairs(&reference_path, &entry.path(), pairs)?;
+            } else if file_type.is_file() {
+                  pairs.push((reference_path, entry.path()));
+            }
+      }
+      Ok(())
+}
+
+impl ReplaceModule {
+      pub fn new(method: ReplaceMethod, verify: bool, fallback: Option<ReplaceMethod>) -> ReplaceModule {
+            ReplaceModule { method, verify, fallback, nb_replaced: 0, nb_skipped: 0, freed: 0 }
+      }
+
+      fn apply(&self, method: ReplaceMethod, reference: &Path, other: &Path, size: u64) -> Result<u64, String> {
+            match method {
+                  ReplaceMethod::Hardlink => replace_with_hardlink(reference, other),
+                  ReplaceMethod::AbsoluteSymlink => replace_with_symlink(reference, other, false, self.verify).map(|_| size),
+                  ReplaceMethod::RelativeSymlink => replace_with_symlink(reference, other, true, self.verify).map(|_| size),
+                  ReplaceMethod::Reflink => match (dedupe(reference, other), self.fallback) {
+                        (Ok(size), _) => Ok(size),
+                        (Err(DedupeError::Failed(reason)), _) => Err(reason),
+                        (Err(DedupeError::Unsupported(reason)), Some(fallback)) => {
+                              eprintln!("!!! Reflink not supported for {} : {}, {} used instead", despecialise(&other.to_string_lossy()), reason, fallback.label());
+                              self.apply(fallback, reference, other, size)
+                        },
+                        (Err(DedupeError::Unsupported(reason)), None) => Err(format!("reflink not supported : {}", reason))
+                  }
+            }
+      }
+
+      fn replace(&mut self, reference: &Path, other: &Path, size: u64) {
+            match self.apply(self.method, reference, other, size) {
+                  Ok(size) => {
+                        eprintln!("{} LINKED TO {}", despecialise(&other.to_string_lossy()), despecialise(&reference.to_string_lossy()));
+                        self.nb_replaced += 1;
+                        self.freed += size;
+                  },
+                  Err(reason) => {
+                        eprintln!("!!! {} not replaced : {}", despecialise(&other.to_string_lossy()), reason);
+                        self.nb_skipped += 1;
+                  }
+            }
+      }
+}
+
+impl OutputModule for ReplaceModule {
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            eprintln!("!!! Doublon {} <-> {}", despecialise(&first.name), despecialise(&second.name));
+      }
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            let size = other.size;
+            let (reference, other) = (Path::new(&reference.name), Path::new(&other.name));
+            if [ReplaceMethod::Hardlink, ReplaceMethod::Reflink].contains(&self.method) && other.is_dir() {
+                  // Equivalent directories may have different names when names are not hashed,
+                  // files without counterpart at the same path are skipped by content check
+                  let mut pairs = Vec::new();
+                  if let Err(reason) = file_pairs(reference, other, &mut pairs) {
+                        eprintln!("!!! {} not replaced : {}", despecialise(&other.to_string_lossy()), reason);
+                        self.nb_skipped += 1;
+                        return Ok(true);
+                  }
+                  pairs.iter().for_each(|(reference, other)| self.replace(reference, other, 0));
+            } else {
+                  self.replace(reference, other, size);
+            }
+            Ok(true)
+      }
+      fn treat_hardlinked(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            eprintln!("=== Hardlinked {} <-> {}", despecialise(&first.name), despecialise(&second.name));
+      }
+}
+
+impl Drop for ReplaceModule {
+    fn drop(&mut self) {
+        println!("==> {} entries replaced by {}, {} skipped, {} bytes freed", self.nb_replaced, self.method.label(), self.nb_skipped, self.freed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+
+    #[test]
+    fn check_replace_with_hardlink() {
+        let root = Path::new("replace21");
+        fs::create_dir_all(root.join("ref/sub")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth/copy")).expect("Unable to create directory");
+        for (name, content) in [("ref/a", "Same"), ("oth/a", "Same"), ("oth/b", "Diff"), ("ref/sub/c", "Sub"), ("oth/copy/c", "Sub"), ("oth/copy/d", "Alone"), ("ref/e", "Mode"), ("oth/e", "Mode")] {
+            fs::write(root.join(name), content).expect("Unable to write file");
+        }
+        fs::set_permissions(root.join("oth/e"), fs::Permissions::from_mode(0o600)).expect("Unable to set permissions");
+        let inode = |name: &str| fs::metadata(root.join(name)).expect("Unable to get metadata").ino();
+
+        assert_eq!(Ok(4), replace_with_hardlink(&root.join("ref/a"), &root.join("oth/a")));
+        assert_eq!(inode("ref/a"), inode("oth/a"));
+        assert_eq!(Err("already hardlinked".to_string()), replace_with_hardlink(&root.join("ref/a"), &root.join("oth/a")));
+        assert_eq!(Err("contents differ".to_string()), replace_with_hardlink(&root.join("ref/a"), &root.join("oth/b")));
+        assert_eq!(Err("permissions or ownership differ".to_string()), replace_with_hardlink(&root.join("ref/e"), &root.join("oth/e")));
+        {
+            let mut module = ReplaceModule::new(ReplaceMethod::Hardlink, false, None);
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("replace21/ref/sub", "", 0, 0, 0), &FileTreeInfo::for_test("replace21/oth/copy", "", 0, 0, 0)).unwrap());
+            assert_eq!((1, 1, 3), (module.nb_replaced, module.nb_skipped, module.freed));
+        }
+        // Unreadable directory is skipped, or its file when running as root
+        fs::create_dir_all(root.join("oth/private")).expect("Unable to create directory");
+        fs::write(root.join("oth/private/f"), "Private").expect("Unable to write file");
+        fs::set_permissions(root.join("oth/private"), fs::Permissions::from_mode(0o000)).expect("Unable to set permissions");
+        {
+            let mut module = ReplaceModule::new(ReplaceMethod::Hardlink, false, None);
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("replace21/ref/private", "", 0, 0, 0), &FileTreeInfo::for_test("replace21/oth/private", "", 0, 0, 0)).unwrap());
+            assert_eq!((0, 1), (module.nb_replaced, module.nb_skipped));
+        }
+        fs::set_permissions(root.join("oth/private"), fs::Permissions::from_mode(0o755)).expect("Unable to set permissions");
+        fs::remove_dir_all(root.join("oth/private")).expect("Unable to remove directory");
+        assert_eq!(inode("ref/sub/c"), inode("oth/copy/c"));
+        assert_eq!("Alone", fs::read_to_string(root.join("oth/copy/d")).expect("Unable to read file"));
+        // No temporary link left behind
+        assert_eq!(4, fs::read_dir(root.join("oth")).expect("Unable to read directory").count());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+
+    #[test]
+    fn check_replace_with_symlink() {
+        let root = Path::new("symlink22");
+        fs::create_dir_all(root.join("ref/sub")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth/dir/copy")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth/changed")).expect("Unable to create directory");
+        for (name, content) in [("ref/a", "Same"), ("oth/a", "Same"), ("ref/sub/c", "Sub"), ("oth/dir/copy/c", "Sub"), ("oth/b", "Diff"), ("oth/changed/c", "Changed")] {
+            fs::write(root.join(name), content).expect("Unable to write file");
+        }
+        assert_eq!(Ok(()), replace_with_symlink(&root.join("ref/a"), &root.join("oth/a"), true, true));
+        assert_eq!(Path::new("../ref/a"), fs::read_link(root.join("oth/a")).expect("Not a link"));
+        assert_eq!("Same", fs::read_to_string(root.join("oth/a")).expect("Unable to read file"));
+        assert_eq!(Err("already a symbolic link".to_string()), replace_with_symlink(&root.join("ref/a"), &root.join("oth/a"), true, true));
+        assert!(replace_with_symlink(&root.join("ref/a"), &root.join("oth/b"), false, true).is_err());
+        assert_eq!("Diff", fs::read_to_string(root.join("oth/b")).expect("Unable to read file"));
+        // Directory is checked even when verification is not requested
+        assert_eq!(Err(format!("link to {} does not give the same content", fs::canonicalize(root.join("ref/sub")).unwrap().display())),
+                   replace_with_symlink(&root.join("ref/sub"), &root.join("oth/changed"), false, false));
+        assert_eq!("Changed", fs::read_to_string(root.join("oth/changed/c")).expect("Unable to read file"));
+        fs::remove_dir_all(root.join("oth/changed")).expect("Unable to remove directory");
+        {
+            let mut module = ReplaceModule::new(ReplaceMethod::AbsoluteSymlink, false, None);
+            let copy = FileTreeInfo::for_test("symlink22/oth/dir/copy", "", 1, 0, 3);
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("symlink22/ref/sub", "", 0, 0, 0), &copy).unwrap());
+            assert_eq!((1, 0, 3), (module.nb_replaced, module.nb_skipped, module.freed));
+        }
+        assert_eq!(fs::canonicalize(root.join("ref/sub")).unwrap(), fs::read_link(root.join("oth/dir/copy")).expect("Not a link"));
+        // Replaced directory and temporary link are removed
+        assert_eq!(1, fs::read_dir(root.join("oth/dir")).expect("Unable to read directory").count());
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+
+    #[test]
+    fn check_reflink() {
+        let root = Path::new("reflink23");
+        fs::create_dir_all(root.join("ref")).expect("Unable to create directory");
+        fs::create_dir_all(root.join("oth")).expect("Unable to create directory");
+        for (name, content) in [("ref/a", "Same"), ("oth/a", "Same"), ("oth/b", "Diff"), ("oth/c", "Longer")] {
+            fs::write(root.join(name), content).expect("Unable to write file");
+        }
+        let inode = |name: &str| fs::metadata(root.join(name)).expect("Unable to get metadata").ino();
+        assert_eq!(Err(DedupeError::Failed("contents differ".to_string())), dedupe(&root.join("ref/a"), &root.join("oth/c")));
+        // Depending on filesystem, kernel finds that contents differ or does not support deduplication
+        assert!(dedupe(&root.join("ref/a"), &root.join("oth/b")).is_err());
+        assert_eq!("Diff", fs::read_to_string(root.join("oth/b")).expect("Unable to read file"));
+        let supported = dedupe(&root.join("ref/a"), &root.join("oth/a"));
+        {
+            let mut module = ReplaceModule::new(ReplaceMethod::Reflink, false, Some(ReplaceMethod::Hardlink));
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("reflink23/ref/a", "", 0, 0, 0), &FileTreeInfo::for_test("reflink23/oth/a", "", 0, 0, 0)).unwrap());
+            assert_eq!(1, module.nb_replaced);
+        }
+        match supported {
+            Ok(size) => { assert_eq!(4, size); assert_ne!(inode("ref/a"), inode("oth/a")) },
+            Err(DedupeError::Unsupported(_)) => assert_eq!(inode("ref/a"), inode("oth/a")),
+            Err(e) => panic!("Unexpected error {:?}", e)
+        }
+        assert_eq!("Same", fs::read_to_string(root.join("oth/a")).expect("Unable to read file"));
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+
+    #[test]
+    fn check_relative_target() {
+        assert_eq!(Path::new("../ref/a"), relative_target(Path::new("/data/oth"), Path::new("/data/ref/a")));
+        assert_eq!(Path::new("ref"), relative_target(Path::new("/data"), Path::new("/data/ref")));
+        assert_eq!(Path::new("../../x"), relative_target(Path::new("/a/b"), Path::new("/x")));
+    }
+
+    #[test]
+    fn check_same_content() {
+        let root = Path::new("content21");
+        fs::create_dir_all(root).expect("Unable to create directory");
+        for (name, content) in [("a", "Content"), ("b", "Content"), ("c", "Content and more"), ("d", "")] {
+            fs::write(root.join(name), content).expect("Unable to write file");
+        }
+        assert_eq!(Ok(true), same_content(&root.join("a"), &root.join("b")));
+        assert_eq!(Ok(false), same_content(&root.join("a"), &root.join("c")));
+        assert_eq!(Ok(false), same_content(&root.join("c"), &root.join("a")));
+        assert_eq!(Ok(false), same_content(&root.join("d"), &root.join("a")));
+        assert!(fs::remove_dir_all(root).is_ok());
+    }
+}
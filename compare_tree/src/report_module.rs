@@ -0,0 +1,148 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+
+use crate::output_module::OutputModule;
+use crate::ct_utils::json_string;
+use crate::filetree_info::FileTreeInfo;
+
+/// Version of report records, to be increased when a field is removed or changes meaning
+pub const REPORT_VERSION: u32 = 1;
+
+/// Write results as NDJSON, one record per line, so that they are never kept in memory.
+/// First record describes the report and last one summarises it
+pub struct ReportModule {
+      filename: String,
+      output_file: BufWriter<File>,
+      /// Internal duplicates sharing the same SHA1, they are reported one after the other
+      group: Vec<FileTreeInfo>,
+      nb_duplicated: u64,
+      duplicated_size: u64,
+      nb_groups: u64,
+      nb_hardlinked: u64
+}
+
+/// Fields common to all entries having the same SHA1
+fn entry_fields(entry: &FileTreeInfo) -> String {
+      format!(r#""sha1":"{}","kind":"{}","height":{},"nb_item":{},"size":{}"#, entry.sha1, entry.kind.name(), entry.height, entry.nb_item, entry.size)
+}
+
+impl ReportModule {
+      pub fn new(filename: &str, reference: &str, other: &str) -> ReportModule {
+            let file = File::create(filename).unwrap_or_else(|e| panic!("Unable to create file {} : {}", filename, e));
+            let mut module = ReportModule { filename: filename.to_string(), output_file: BufWriter::new(file), group: Vec::new(), nb_duplicated: 0, duplicated_size: 0, nb_groups: 0, nb_hardlinked: 0 };
+            module.write_record(format!(r#"{{"record":"header","version":{},"reference":{},"other":{}}}"#, REPORT_VERSION, json_string(reference), json_string(other)));
+            module
+      }
+
+      fn write_record(&mut self, record: String) {
+            self.try_write_record(record).unwrap_or_else(|e| panic!("Unable to write in file {} : {}", self.filename, e));
+      }
+
+      fn try_write_record(&mut self, record: String) -> io::Result<()> {
+            self.output_file.write_all(format!("{}\n", record).as_bytes())
+      }
+
+      fn flush_group(&mut self) -> io::Result<()> {
+            if self.group.is_empty() {
+                  return Ok(());
+            }
+            let paths = self.group.iter().map(|e| json_string(&e.name)).collect::<Vec<String>>().join(",");
+            let record = format!(r#"{{"record":"internal_duplicates",{},"paths":[{}]}}"#, entry_fields(&self.group[0]), paths);
+            self.try_write_record(record)?;
+            self.nb_groups += 1;
+            self.group.clear();
+            Ok(())
+      }
+}
+
+impl OutputModule for ReportModule {
+      fn treat_internal_doublon(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            if self.group.first().is_none_or(|e| e.sha1 != first.sha1) {
+                  self.flush_group().unwrap_or_else(|e| panic!("Unable to write in file {} : {}", self.filename, e));
+            }
+            for entry in [first, second] {
+                  if !self.group.iter().any(|e| e.name == entry.name) {
+                        self.group.push(entry.clone());
+                  }
+            }
+      }
+      fn treat_duplicated(&mut self, reference: &FileTreeInfo, other: &FileTreeInfo) -> Result<bool, String> {
+            self.flush_group().unwrap_or_else(|e| panic!("Unable to write in file {} : {}", self.filename, e));
+            self.write_record(format!(r#"{{"record":"duplicate","reference":{},"other":{},{}}}"#, json_string(&reference.name), json_string(&other.name), entry_fields(other)));
+            self.nb_duplicated += 1;
+            self.duplicated_size += other.size;
+            Ok(true)
+      }
+      fn treat_hardlinked(&mut self, first: &FileTreeInfo, second: &FileTreeInfo) {
+            self.write_record(format!(r#"{{"record":"hardlinked","first":{},"second":{},{}}}"#, json_string(&first.name), json_string(&second.name), entry_fields(second)));
+            self.nb_hardlinked += 1;
+      }
+}
+
+impl Drop for ReportModule {
+    fn drop(&mut self) {
+        let result = self.flush_group().and_then(|_| {
+            let summary = format!(r#"{{"record":"summary","duplicates":{},"duplicated_size":{},"internal_duplicate_groups":{},"hardlinked":{}}}"#,
+                                  self.nb_duplicated, self.duplicated_size, self.nb_groups, self.nb_hardlinked);
+            self.try_write_record(summary)
+        }).and_then(|_| self.output_file.flush());
+        if let Err(e) = result {
+            eprintln!("!!! Unable to write in file {} : {}", self.filename, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use crate::sha1;
+
+
+    #[test]
+    fn check_report_module() {
+        let name = "report.ndjson";
+        let a = FileTreeInfo::for_test("oth/a", "a", 0, 0, 1);
+        let b = FileTreeInfo::for_test("oth/b", "b", 0, 0, 2);
+        {
+            let mut module = ReportModule::new(name, "ref", "oth");
+            module.treat_internal_doublon(&a, &FileTreeInfo::for_test("oth/a2", "a", 0, 0, 1));
+            module.treat_internal_doublon(&FileTreeInfo::for_test("oth/a2", "a", 0, 0, 1), &FileTreeInfo::for_test("oth/\"a3\"", "a", 0, 0, 1));
+            module.treat_hardlinked(&b, &FileTreeInfo::for_test("oth/b2", "b", 0, 0, 2));
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("ref/a", "a", 0, 0, 1), &a).unwrap());
+            assert!(module.treat_duplicated(&FileTreeInfo::for_test("ref/b", "b", 0, 0, 2), &b).unwrap());
+        }
+        let contents = fs::read_to_string(name).expect("Unable to read report");
+        let sha1_a = sha1::compute_sha1(Vec::from("a"));
+        let sha1_b = sha1::compute_sha1(Vec::from("b"));
+        let expected = vec!(r#"{"record":"header","version":1,"reference":"ref","other":"oth"}"#.to_string(),
+                            format!(r#"{{"record":"hardlinked","first":"oth/b","second":"oth/b2","sha1":"{}","kind":"file","height":0,"nb_item":0,"size":2}}"#, sha1_b),
+                            format!(r#"{{"record":"internal_duplicates","sha1":"{}","kind":"file","height":0,"nb_item":0,"size":1,"paths":["oth/a","oth/a2","oth/\"a3\""]}}"#, sha1_a),
+                            format!(r#"{{"record":"duplicate","reference":"ref/a","other":"oth/a","sha1":"{}","kind":"file","height":0,"nb_item":0,"size":1}}"#, sha1_a),
+                            format!(r#"{{"record":"duplicate","reference":"ref/b","other":"oth/b","sha1":"{}","kind":"file","height":0,"nb_item":0,"size":2}}"#, sha1_b),
+                            r#"{"record":"summary","duplicates":2,"duplicated_size":3,"internal_duplicate_groups":1,"hardlinked":1}"#.to_string());
+        assert_eq!(expected, contents.lines().map(String::from).collect::<Vec<String>>());
+        assert!(fs::remove_file(name).is_ok());
+        // Write error is reported without panicking when module is dropped
+        drop(ReportModule::new("/dev/full", "ref", "oth"));
+    }
+}
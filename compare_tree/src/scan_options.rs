@@ -0,0 +1,113 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use std::fs;
+use std::path::Path;
+
+use crate::filter;
+
+/// Version of dump line format
+const DUMP_VERSION: u32 = 6;
+
+/// Options influencing the content of a dump. They are recorded in dump header
+/// so that a dump generated with other options is not reused
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScanOptions {
+    /// Directory hash includes names of its children
+    pub hash_names: bool,
+    /// Directory hash includes type of its children, only meaningful with hash_names
+    pub hash_types: bool,
+    /// Patterns of entries to ignore, using gitignore syntax
+    pub excludes: Vec<String>,
+    /// When not empty only files matching one of these patterns are kept
+    pub includes: Vec<String>,
+    /// Honour .gitignore files in addition to .compare_tree_ignore files
+    pub gitignore: bool,
+    /// Do not descend in directories located on another device than the scanned root
+    pub one_filesystem: bool,
+    /// FIFOs, sockets and device nodes are part of directory hashes instead of being skipped
+    pub hash_special: bool,
+    /// Links are replaced by their target, links to directories are descended into
+    pub follow_links: bool,
+    /// Unreadable entries are recorded as errors instead of aborting the scan
+    pub keep_going: bool,
+    /// Number of times a file modified while being hashed is hashed again before being
+    /// flagged as unstable. Not recorded in header as content of stable files does not depend on it
+    pub retries: u32,
+    /// Canonical paths of directories skipped during scan. Used to exclude a compared tree nested in the other one
+    pub excluded_paths: Vec<String>
+}
+
+impl Default for ScanOptions {
+    fn default() -> ScanOptions {
+        ScanOptions { hash_names: false, hash_types: false, excludes: Vec::new(), includes: Vec::new(), gitignore: false,
+                      one_filesystem: false, hash_special: false, follow_links: false, keep_going: false, retries: 2,
+                      excluded_paths: Vec::new() }
+    }
+}
+
+impl ScanOptions {
+    /// Options of the scan of root. Excluded paths containing root only concern the scan of
+    /// an outer tree, they are removed so that the dump of root does not depend on them
+    pub fn for_root(&self, root: &str) -> ScanOptions {
+        let excluded_paths = match fs::canonicalize(root) {
+            Ok(canonical_root) => self.excluded_paths.iter().filter(|p| !canonical_root.starts_with(Path::new(p))).cloned().collect(),
+            Err(_) => Vec::new()
+        };
+        ScanOptions { excluded_paths, ..self.clone() }
+    }
+
+    pub fn header(&self) -> String {
+        let hash_mode = if !self.hash_names {
+            "content"
+        } else if self.hash_types {
+            "names+types"
+        } else {
+            "names"
+        };
+        let mut ignore_files = vec!(filter::IGNORE_FILE);
+        if self.gitignore {
+            ignore_files.push(filter::GIT_IGNORE_FILE);
+        }
+        format!("# compare_tree dump v{} hash={} exclude={:?} include={:?} ignore_files={:?} one_filesystem={} special={} links={} errors={} excluded_paths={:?}",
+                DUMP_VERSION, hash_mode, self.excludes, self.includes, ignore_files, self.one_filesystem,
+                if self.hash_special { "hash" } else { "skip" }, if self.follow_links { "follow" } else { "hash" },
+                if self.keep_going { "record" } else { "abort" }, self.excluded_paths)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn check_header() {
+        assert_eq!(r#"# compare_tree dump v6 hash=content exclude=[] include=[] ignore_files=[".compare_tree_ignore"] one_filesystem=false special=skip links=hash errors=abort excluded_paths=[]"#, ScanOptions::default().header());
+        assert_eq!(r#"# compare_tree dump v6 hash=names exclude=[] include=[] ignore_files=[".compare_tree_ignore"] one_filesystem=false special=skip links=hash errors=abort excluded_paths=[]"#, ScanOptions { hash_names: true, ..ScanOptions::default() }.header());
+        assert_eq!(r#"# compare_tree dump v6 hash=names+types exclude=["*.o", ".git/"] include=[] ignore_files=[".compare_tree_ignore", ".gitignore"] one_filesystem=true special=hash links=follow errors=record excluded_paths=["other/reference"]"#,
+                   ScanOptions { hash_names: true, hash_types: true, excludes: vec!("*.o".to_string(), ".git/".to_string()), gitignore: true, one_filesystem: true, hash_special: true, follow_links: true, keep_going: true, excluded_paths: vec!("other/reference".to_string()), ..ScanOptions::default() }.header());
+    }
+
+    #[test]
+    fn check_for_root() {
+        let src = fs::canonicalize("src").expect("Unable to canonicalise").to_str().expect("to_str() issue").to_string();
+        let options = ScanOptions { excluded_paths: vec!(src.clone()), ..ScanOptions::default() };
+        assert_eq!(options, options.for_root("."));
+        assert_eq!(ScanOptions::default(), options.for_root("src"));
+        assert_eq!(ScanOptions::default(), options.for_root("src/../src"));
+    }
+}
@@ -0,0 +1,58 @@
+/*    This file is part of compare_tree
+      Copyright (C) 2025  Julien Thevenon ( julien_thevenon at yahoo.fr )
+
+      This program is free software: you can redistribute it and/or modify
+      it under the terms of the GNU General Public License as published by
+      the Free Software Foundation, either version 3 of the License, or
+      (at your option) any later version.
+
+      This program is distributed in the hope that it will be useful,
+      but WITHOUT ANY WARRANTY; without even the implied warranty of
+      MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+      GNU General Public License for more details.
+
+      You should have received a copy of the GNU General Public License
+      along with this program.  If not, see <http://www.gnu.org/licenses/>
+*/
+
+use crate::scan_options::ScanOptions;
+
+/// What was met during a scan without being part of the dump as regular entries
+#[derive(Debug, PartialEq, Default)]
+pub struct ScanSummary {
+    /// FIFOs, sockets and device nodes found
+    pub nb_special: u32,
+    /// Directories not scanned because located on another filesystem
+    pub skipped_mount_points: Vec<String>,
+    /// Links whose target does not exist, they are hashed by target path
+    pub dangling_links: Vec<String>,
+    /// Followed links pointing to one of their ancestors, they are hashed by target path
+    pub link_loops: Vec<String>,
+    /// Unreadable entries with the error met
+    pub errors: Vec<(String, String)>,
+    /// Files still modified while being hashed after all retries
+    pub unstable: Vec<String>
+}
+
+impl ScanSummary {
+    pub fn display(&self, name: &str, options: &ScanOptions) {
+        println!("==> Scan summary for {}", name);
+        println!("===> {} special files {}", self.nb_special, if options.hash_special { "hashed" } else { "skipped" });
+        if options.one_filesystem {
+            println!("===> {} mount points skipped", self.skipped_mount_points.len());
+            self.skipped_mount_points.iter().for_each(|name| println!("     {}", name));
+        }
+        println!("===> {} dangling links", self.dangling_links.len());
+        self.dangling_links.iter().for_each(|name| println!("     {}", name));
+        if options.follow_links {
+            println!("===> {} link loops not followed", self.link_loops.len());
+            self.link_loops.iter().for_each(|name| println!("     {}", name));
+        }
+        if options.keep_going {
+            println!("===> {} read errors", self.errors.len());
+            self.errors.iter().for_each(|(name, error)| println!("     {} : {}", name, error));
+        }
+        println!("===> {} files modified during scan", self.unstable.len());
+        self.unstable.iter().for_each(|name| println!("     {}", name));
+    }
+}
@@ -16,7 +16,7 @@
 */
 use std::fmt;
 
-#[derive(PartialEq, PartialOrd, Eq, Ord, Clone)]
+#[derive(PartialEq, PartialOrd, Eq, Ord, Clone, Hash)]
 #[derive(Debug)]
 pub struct Sha1Key {
     words: [u32; 5]